@@ -0,0 +1,310 @@
+/*
+ * A minimal libretro host for trying out the core without RetroArch.
+ *
+ *     cargo build --release --no-default-features --features libretro
+ *     cc -o host libretro/host.c -ldl
+ *     ./host target/release/libpacman.so path/to/roms/pacman.6e [frames]
+ *
+ * It runs the core for the given number of frames (600 by default) with a
+ * coin inserted and player one started partway through, then saves a state,
+ * runs on, loads the state and checks that the same frames come out again.
+ * The last frame is written to last-frame.ppm.
+ */
+
+#include <dlfcn.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define RETRO_DEVICE_JOYPAD 1
+#define RETRO_DEVICE_ID_JOYPAD_SELECT 2
+#define RETRO_DEVICE_ID_JOYPAD_START 3
+#define RETRO_ENVIRONMENT_SET_PIXEL_FORMAT 10
+#define RETRO_PIXEL_FORMAT_XRGB8888 1
+
+struct retro_system_info {
+    const char *library_name;
+    const char *library_version;
+    const char *valid_extensions;
+    bool need_fullpath;
+    bool block_extract;
+};
+
+struct retro_game_geometry {
+    unsigned base_width;
+    unsigned base_height;
+    unsigned max_width;
+    unsigned max_height;
+    float aspect_ratio;
+};
+
+struct retro_system_timing {
+    double fps;
+    double sample_rate;
+};
+
+struct retro_system_av_info {
+    struct retro_game_geometry geometry;
+    struct retro_system_timing timing;
+};
+
+struct retro_game_info {
+    const char *path;
+    const void *data;
+    size_t size;
+    const char *meta;
+};
+
+typedef bool (*retro_environment_t)(unsigned cmd, void *data);
+typedef void (*retro_video_refresh_t)(const void *data, unsigned width, unsigned height,
+                                      size_t pitch);
+typedef void (*retro_audio_sample_t)(int16_t left, int16_t right);
+typedef size_t (*retro_audio_sample_batch_t)(const int16_t *data, size_t frames);
+typedef void (*retro_input_poll_t)(void);
+typedef int16_t (*retro_input_state_t)(unsigned port, unsigned device, unsigned index,
+                                       unsigned id);
+
+static struct {
+    unsigned (*api_version)(void);
+    void (*set_environment)(retro_environment_t);
+    void (*set_video_refresh)(retro_video_refresh_t);
+    void (*set_audio_sample)(retro_audio_sample_t);
+    void (*set_audio_sample_batch)(retro_audio_sample_batch_t);
+    void (*set_input_poll)(retro_input_poll_t);
+    void (*set_input_state)(retro_input_state_t);
+    void (*init)(void);
+    void (*deinit)(void);
+    void (*get_system_info)(struct retro_system_info *);
+    void (*get_system_av_info)(struct retro_system_av_info *);
+    bool (*load_game)(const struct retro_game_info *);
+    void (*unload_game)(void);
+    void (*run)(void);
+    size_t (*serialize_size)(void);
+    bool (*serialize)(void *, size_t);
+    bool (*unserialize)(const void *, size_t);
+} core;
+
+static unsigned frame_count;
+static unsigned frame_width, frame_height;
+static size_t frame_pitch;
+static uint32_t *last_frame;
+static uint32_t frame_hash;
+static size_t audio_frames;
+static int16_t audio_peak;
+
+static bool environment(unsigned cmd, void *data) {
+    if (cmd == RETRO_ENVIRONMENT_SET_PIXEL_FORMAT) {
+        return *(unsigned *)data == RETRO_PIXEL_FORMAT_XRGB8888;
+    }
+    return false;
+}
+
+/* FNV-1a over the picture, to compare frames cheaply */
+static void video_refresh(const void *data, unsigned width, unsigned height, size_t pitch) {
+    const uint8_t *bytes = data;
+    size_t i;
+
+    frame_width = width;
+    frame_height = height;
+    frame_pitch = pitch;
+    frame_hash = 2166136261u;
+    for (i = 0; i < height * pitch; i++) {
+        frame_hash = (frame_hash ^ bytes[i]) * 16777619u;
+    }
+    memcpy(last_frame, data, height * pitch);
+}
+
+static void audio_sample(int16_t left, int16_t right) {
+    (void)left;
+    (void)right;
+    audio_frames++;
+}
+
+static size_t audio_sample_batch(const int16_t *data, size_t frames) {
+    size_t i;
+
+    for (i = 0; i < frames * 2; i++) {
+        int16_t sample = data[i] < 0 ? -data[i] : data[i];
+        if (sample > audio_peak) {
+            audio_peak = sample;
+        }
+    }
+    audio_frames += frames;
+    return frames;
+}
+
+static void input_poll(void) {}
+
+/* a coin at 2 seconds and player one's start at 3, held for a few frames */
+static int16_t input_state(unsigned port, unsigned device, unsigned index, unsigned id) {
+    (void)index;
+    if (port != 0 || device != RETRO_DEVICE_JOYPAD) {
+        return 0;
+    }
+    if (id == RETRO_DEVICE_ID_JOYPAD_SELECT) {
+        return frame_count >= 120 && frame_count < 125;
+    }
+    if (id == RETRO_DEVICE_ID_JOYPAD_START) {
+        return frame_count >= 180 && frame_count < 185;
+    }
+    return 0;
+}
+
+static void *load_symbol(void *library, const char *name) {
+    void *symbol = dlsym(library, name);
+    if (!symbol) {
+        fprintf(stderr, "Missing %s in the core\n", name);
+        exit(1);
+    }
+    return symbol;
+}
+
+static void run_frames(unsigned frames, uint32_t *hashes) {
+    unsigned i;
+
+    for (i = 0; i < frames; i++) {
+        core.run();
+        frame_count++;
+        if (hashes) {
+            hashes[i] = frame_hash;
+        }
+    }
+}
+
+static void write_ppm(const char *path) {
+    FILE *file = fopen(path, "wb");
+    unsigned x, y;
+
+    if (!file) {
+        perror(path);
+        return;
+    }
+    fprintf(file, "P6\n%u %u\n255\n", frame_width, frame_height);
+    for (y = 0; y < frame_height; y++) {
+        for (x = 0; x < frame_width; x++) {
+            uint32_t pixel = last_frame[y * frame_pitch / 4 + x];
+            fputc(pixel >> 16 & 0xFF, file);
+            fputc(pixel >> 8 & 0xFF, file);
+            fputc(pixel & 0xFF, file);
+        }
+    }
+    fclose(file);
+}
+
+int main(int argc, char **argv) {
+    struct retro_system_info info;
+    struct retro_system_av_info av_info;
+    struct retro_game_info game = {0};
+    unsigned frames = 600, replay, i;
+    uint32_t *expected, *replayed;
+    void *library, *state;
+    size_t state_size;
+    int status = 0;
+
+    if (argc < 3) {
+        fprintf(stderr, "usage: %s core.so rom-file [frames]\n", argv[0]);
+        return 1;
+    }
+    if (argc > 3) {
+        frames = (unsigned)atoi(argv[3]);
+    }
+    if (frames < 2) {
+        fprintf(stderr, "Need at least 2 frames\n");
+        return 1;
+    }
+
+    library = dlopen(argv[1], RTLD_NOW);
+    if (!library) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+    *(void **)&core.api_version = load_symbol(library, "retro_api_version");
+    *(void **)&core.set_environment = load_symbol(library, "retro_set_environment");
+    *(void **)&core.set_video_refresh = load_symbol(library, "retro_set_video_refresh");
+    *(void **)&core.set_audio_sample = load_symbol(library, "retro_set_audio_sample");
+    *(void **)&core.set_audio_sample_batch = load_symbol(library, "retro_set_audio_sample_batch");
+    *(void **)&core.set_input_poll = load_symbol(library, "retro_set_input_poll");
+    *(void **)&core.set_input_state = load_symbol(library, "retro_set_input_state");
+    *(void **)&core.init = load_symbol(library, "retro_init");
+    *(void **)&core.deinit = load_symbol(library, "retro_deinit");
+    *(void **)&core.get_system_info = load_symbol(library, "retro_get_system_info");
+    *(void **)&core.get_system_av_info = load_symbol(library, "retro_get_system_av_info");
+    *(void **)&core.load_game = load_symbol(library, "retro_load_game");
+    *(void **)&core.unload_game = load_symbol(library, "retro_unload_game");
+    *(void **)&core.run = load_symbol(library, "retro_run");
+    *(void **)&core.serialize_size = load_symbol(library, "retro_serialize_size");
+    *(void **)&core.serialize = load_symbol(library, "retro_serialize");
+    *(void **)&core.unserialize = load_symbol(library, "retro_unserialize");
+
+    core.get_system_info(&info);
+    printf("%s %s, API version %u\n", info.library_name, info.library_version,
+           core.api_version());
+
+    core.set_environment(environment);
+    core.set_video_refresh(video_refresh);
+    core.set_audio_sample(audio_sample);
+    core.set_audio_sample_batch(audio_sample_batch);
+    core.set_input_poll(input_poll);
+    core.set_input_state(input_state);
+    core.init();
+
+    game.path = argv[2];
+    if (!core.load_game(&game)) {
+        fprintf(stderr, "Couldn't load %s\n", argv[2]);
+        return 1;
+    }
+
+    core.get_system_av_info(&av_info);
+    printf("%ux%u, aspect %.4f, %.3f fps, %.0f Hz\n", av_info.geometry.base_width,
+           av_info.geometry.base_height, av_info.geometry.aspect_ratio, av_info.timing.fps,
+           av_info.timing.sample_rate);
+    last_frame = calloc(av_info.geometry.max_height, av_info.geometry.max_width * 4);
+
+    /* run the first half, save, run the second half, then load and check
+       the second half plays out the same. input depends on frame_count, so
+       it's replayed too */
+    replay = frames / 2;
+    expected = calloc(frames - replay, sizeof(uint32_t));
+    replayed = calloc(frames - replay, sizeof(uint32_t));
+    run_frames(replay, NULL);
+
+    state_size = core.serialize_size();
+    state = malloc(state_size);
+    if (!core.serialize(state, state_size)) {
+        fprintf(stderr, "Couldn't save a state\n");
+        return 1;
+    }
+    run_frames(frames - replay, expected);
+    printf("%u frames, %zu audio frames, peak %d, state %zu bytes\n", frame_count, audio_frames,
+           audio_peak, state_size);
+    write_ppm("last-frame.ppm");
+
+    if (!core.unserialize(state, state_size)) {
+        fprintf(stderr, "Couldn't load the state\n");
+        return 1;
+    }
+    frame_count = replay;
+    run_frames(frames - replay, replayed);
+    for (i = 0; i < frames - replay; i++) {
+        if (expected[i] != replayed[i]) {
+            fprintf(stderr, "Frame %u differs after loading the state\n", replay + i + 1);
+            status = 1;
+            break;
+        }
+    }
+    if (status == 0) {
+        printf("Loading the state replayed %u frames identically\n", frames - replay);
+    }
+
+    core.unload_game();
+    core.deinit();
+    dlclose(library);
+    free(state);
+    free(expected);
+    free(replayed);
+    free(last_frame);
+    return status;
+}
@@ -0,0 +1,98 @@
+use sound::{Resampler, SAMPLE_RATE};
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::Sdl;
+
+// what we ask SDL for, it may give us something else
+const DESIRED_RATE: i32 = 48000;
+const DEVICE_BUFFER_SAMPLES: u16 = 1024;
+// how much audio we try to keep queued, in seconds
+const TARGET_LATENCY: f64 = 0.05;
+// past this many times the target latency the queue is dropped, which only
+// happens if emulation stalled or ran far ahead
+const MAX_LATENCY_FACTOR: f64 = 4.0;
+// the furthest the output rate is nudged from the device rate to keep the
+// queue at the target. Half a percent of pitch isn't audible
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// Plays WSG samples through an SDL audio queue
+///
+/// Emulated frames and the audio device run off different clocks, so
+/// the resampling rate is continuously adjusted to keep the amount of queued
+/// audio near TARGET_LATENCY instead of letting it slowly drain or grow.
+pub struct AudioOutput {
+    queue: AudioQueue<i16>,
+    resampler: Resampler,
+    // device sample rate
+    rate: f64,
+    muted: bool,
+    buffer: Vec<i16>,
+}
+
+impl AudioOutput {
+    pub fn new(sdl_context: &Sdl, muted: bool) -> Result<Self, String> {
+        let audio = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(DESIRED_RATE),
+            channels: Some(1),
+            samples: Some(DEVICE_BUFFER_SAMPLES),
+        };
+        let queue: AudioQueue<i16> = audio.open_queue(None, &desired)?;
+        let rate = queue.spec().freq;
+        queue.resume();
+
+        Ok(AudioOutput {
+            queue: queue,
+            resampler: Resampler::new(SAMPLE_RATE, rate as u32),
+            rate: rate as f64,
+            muted: muted,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    /// Queues <samples> (at the WSG's 96 kHz) for playback
+    pub fn push(&mut self, samples: &[i16]) {
+        let queued = self.queue.size() as f64 / 2.0;
+        let target = self.rate * TARGET_LATENCY;
+        if queued > target * MAX_LATENCY_FACTOR {
+            self.queue.clear();
+        }
+
+        let output_rate = self.rate * rate_adjustment(queued, target);
+        self.resampler.set_rates(SAMPLE_RATE as f64, output_rate);
+
+        self.buffer.clear();
+        self.resampler.resample(samples, &mut self.buffer);
+        if self.muted {
+            for sample in self.buffer.iter_mut() {
+                *sample = 0;
+            }
+        }
+        self.queue.queue(&self.buffer);
+    }
+}
+
+// more output samples per input sample while the queue is short of the
+// target, fewer while it's over
+fn rate_adjustment(queued: f64, target: f64) -> f64 {
+    let error = ((target - queued) / target).max(-1.0).min(1.0);
+    1.0 + error * MAX_RATE_DELTA
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_adjustment() {
+        assert_eq!(rate_adjustment(2400.0, 2400.0), 1.0);
+        assert_eq!(rate_adjustment(0.0, 2400.0), 1.0 + MAX_RATE_DELTA);
+        assert_eq!(rate_adjustment(1_000_000.0, 2400.0), 1.0 - MAX_RATE_DELTA);
+        assert!(rate_adjustment(1200.0, 2400.0) > 1.0);
+        assert!(rate_adjustment(3600.0, 2400.0) < 1.0);
+    }
+}
@@ -0,0 +1,190 @@
+use cpu::io::TestIO;
+use cpu::mem::Memory;
+use cpu::Z80;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+// CP/M programs are loaded and start at the beginning of the TPA
+const TPA_START: u16 = 0x0100;
+// calls to BDOS go through a jump at 0x0005, whose target is also read by
+// programs to find the top of usable memory
+const BDOS_ENTRY: u16 = 0x0005;
+const BDOS_ADDRESS: u16 = 0xFE00;
+// jumping to 0x0000 is a warm boot, which means the program has finished
+const WARM_BOOT: u16 = 0x0000;
+
+/// 64K of flat RAM, which is what a CP/M machine looks like to a program
+pub struct CpmMemory {
+    pub ram: Vec<u8>,
+}
+
+impl CpmMemory {
+    pub fn new() -> Self {
+        CpmMemory {
+            ram: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Memory for CpmMemory {
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write_byte(&mut self, byte: u8, addr: u16) {
+        self.ram[addr as usize] = byte;
+    }
+}
+
+/// The outcome of one instruction group of a test program such as zexdoc,
+/// which prints a line ending in "OK" or "ERROR" for each of them
+pub struct GroupResult {
+    pub name: String,
+    pub passed: bool,
+}
+
+/// A bare bones CP/M machine for running Z80 conformance tests (zexdoc,
+/// zexall and the like) distributed as .COM files.
+///
+/// Only BDOS functions 2 (console output) and 9 (print string) are
+/// provided, which is all these programs need. Everything they print is
+/// captured, see output.
+pub struct Cpm {
+    cpu: Z80,
+    memory: CpmMemory,
+    io: TestIO,
+    output: String,
+    cycles: u64,
+}
+
+impl Cpm {
+    pub fn new() -> Self {
+        Cpm {
+            cpu: Z80::new(),
+            memory: CpmMemory::new(),
+            io: TestIO::new(),
+            output: String::new(),
+            cycles: 0,
+        }
+    }
+
+    /// Loads the .COM file at <path>
+    pub fn load_file(&mut self, path: &Path) -> io::Result<()> {
+        let mut program = Vec::new();
+        File::open(path)?.read_to_end(&mut program)?;
+        self.load(&program);
+        Ok(())
+    }
+
+    /// Loads <program> at 0x0100 and sets up the zero page and the stack
+    /// so that returning from it ends the run
+    pub fn load(&mut self, program: &[u8]) {
+        let start = TPA_START as usize;
+        self.memory.ram[start..start + program.len()].copy_from_slice(program);
+
+        // JP BDOS_ADDRESS, with a RET there in case a call isn't trapped
+        self.memory.ram[BDOS_ENTRY as usize] = 0xC3;
+        self.memory.write_word(BDOS_ADDRESS, BDOS_ENTRY + 1);
+        self.memory.ram[BDOS_ADDRESS as usize] = 0xC9;
+
+        self.cpu.reg.pc = TPA_START;
+        self.cpu.reg.sp = BDOS_ADDRESS;
+        self.cpu.push_stack_16(&mut self.memory, WARM_BOOT);
+    }
+
+    /// Runs the loaded program until it warm boots, or until it calls a
+    /// BDOS function that isn't provided
+    pub fn run(&mut self) -> Result<(), String> {
+        loop {
+            match self.cpu.reg.pc {
+                WARM_BOOT => return Ok(()),
+                BDOS_ENTRY => {
+                    self.bdos()?;
+                    self.cpu.ret(&mut self.memory);
+                }
+                _ => {
+                    self.cycles += self.cpu.run_opcodes(1, &mut self.memory, &mut self.io) as u64;
+                }
+            }
+        }
+    }
+
+    /// Everything the program printed
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// T-states the program took to run
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Results of each instruction group in the order they were printed
+    pub fn groups(&self) -> Vec<GroupResult> {
+        self.output
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                let passed = if line.ends_with("OK") {
+                    true
+                } else if line.contains("ERROR") {
+                    false
+                } else {
+                    return None;
+                };
+                let name = line.split("...").next().unwrap_or(line).trim();
+                Some(GroupResult {
+                    name: name.to_string(),
+                    passed: passed,
+                })
+            })
+            .collect()
+    }
+
+    // handles the BDOS function in C
+    fn bdos(&mut self) -> Result<(), String> {
+        match self.cpu.reg.c {
+            // console output of the character in E
+            2 => self.output.push(self.cpu.reg.e as char),
+            // print the string at DE, terminated by '$'
+            9 => {
+                let mut addr = self.cpu.reg.de();
+                loop {
+                    let c = self.memory.read_byte(addr) as char;
+                    if c == '$' {
+                        break;
+                    }
+                    self.output.push(c);
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            function => return Err(format!("Unsupported BDOS function {}", function)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpm_groups() {
+        let mut cpm = Cpm::new();
+        cpm.output = String::from(
+            "Z80 instruction exerciser\r\n\
+             <adc,sbc> hl,<bc,de,hl,sp>....  OK\r\n\
+             aluop a,nn...................  ERROR **** crc expected:48799360 found:12345678\r\n\
+             Tests complete\r\n",
+        );
+        let groups = cpm.groups();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "<adc,sbc> hl,<bc,de,hl,sp>");
+        assert!(groups[0].passed);
+        assert_eq!(groups[1].name, "aluop a,nn");
+        assert!(!groups[1].passed);
+    }
+}
@@ -7,12 +7,12 @@ pub trait Memory {
 
     /// Read 16 bit word <word> at address <addr>
     fn read_word(&self, addr: u16) -> u16 {
-        (self.read_byte(addr + 1) as u16) << 8 | (self.read_byte(addr) as u16)
+        (self.read_byte(addr.wrapping_add(1)) as u16) << 8 | (self.read_byte(addr) as u16)
     }
 
     /// Write 16 bit word <word> to address <addr>
     fn write_word(&mut self, word: u16, addr: u16) {
-        self.write_byte((word >> 8) as u8, addr + 1);
+        self.write_byte((word >> 8) as u8, addr.wrapping_add(1));
         self.write_byte(word as u8, addr);
     }
 }
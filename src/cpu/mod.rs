@@ -1,12 +1,16 @@
+#[cfg(test)]
+mod cpm;
 pub mod io;
 pub mod mem;
 mod reg;
+mod timing;
 
 use std::fmt;
 
 use cpu::io::InputOutput;
 use cpu::mem::Memory;
 use cpu::reg::Registers;
+use savestate::{StateReader, StateWriter};
 
 // TODO make IO into a trait
 pub struct Z80 {
@@ -14,10 +18,21 @@ pub struct Z80 {
     altreg: Registers,
 
     halted: bool,
-    interrupts_enabled: bool,
+    // interrupt flip-flops. IFF1 masks interrupts and IFF2 keeps a copy
+    // of it while an NMI is being serviced
+    iff1: bool,
+    iff2: bool,
+    // set by EI, which doesn't let interrupts in until after the next
+    // instruction
+    ei_delay: bool,
     interrupt: bool,
     interrupt_data: u8,
     interrupt_mode: u8,
+    nmi: bool,
+
+    // address of the (HL) operand of the current opcode, which is
+    // (IX+d) or (IY+d) after a DD or FD prefix
+    operand_addr: u16,
 }
 
 impl Z80 {
@@ -27,34 +42,93 @@ impl Z80 {
             altreg: Registers::new(),
 
             halted: false,
-            interrupts_enabled: true,
+            iff1: false,
+            iff2: false,
+            ei_delay: false,
             interrupt: false,
             interrupt_data: 0,
             interrupt_mode: 0,
+            nmi: false,
+
+            operand_addr: 0,
         };
         cpu.reg.sp = 0x4FEF;
         cpu
     }
 
+    /// Requests a maskable interrupt. <data> is the byte the interrupting
+    /// device puts on the data bus: an opcode in mode 0, ignored in mode 1
+    /// and the low byte of the vector table address in mode 2.
+    ///
+    /// The request stays pending until the CPU accepts it.
     pub fn interrupt(&mut self, data: u8) {
-        self.halted = false;
         self.interrupt = true;
         self.interrupt_data = data;
     }
 
-    /// Runs a specified number of opcodes
+    /// Withdraws a maskable interrupt request the CPU hasn't accepted yet
+    pub fn clear_interrupt(&mut self) {
+        self.interrupt = false;
+    }
+
+    /// Requests a non maskable interrupt, which is accepted at the next
+    /// instruction boundary regardless of IFF1
+    pub fn nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    /// Appends the registers and interrupt state to <state>
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.reg.save_state(state);
+        self.altreg.save_state(state);
+        state.bool(self.halted);
+        state.bool(self.iff1);
+        state.bool(self.iff2);
+        state.bool(self.ei_delay);
+        state.bool(self.interrupt);
+        state.u8(self.interrupt_data);
+        state.u8(self.interrupt_mode);
+        state.bool(self.nmi);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.reg.load_state(state)?;
+        self.altreg.load_state(state)?;
+        self.halted = state.bool()?;
+        self.iff1 = state.bool()?;
+        self.iff2 = state.bool()?;
+        self.ei_delay = state.bool()?;
+        self.interrupt = state.bool()?;
+        self.interrupt_data = state.u8()?;
+        self.interrupt_mode = state.u8()?;
+        self.nmi = state.bool()?;
+        Ok(())
+    }
+
+    /// Runs a specified number of opcodes and returns the number of
+    /// T-states they took
     pub fn run_opcodes(
         &mut self,
         iters: usize,
         memory: &mut Memory,
         io: &mut InputOutput,
     ) -> usize {
-        if self.halted {
-            return 4;
-        }
-
         let mut cycles = 0;
-        for i in 0..iters {
+        for _ in 0..iters {
+            if let Some(c) = self.handle_interrupts(memory, io) {
+                cycles += c;
+                continue;
+            }
+            // the instruction after EI has started, interrupts can be taken
+            // after it
+            self.ei_delay = false;
+
+            if self.halted {
+                self.refresh();
+                cycles += timing::HALT;
+                continue;
+            }
+
             let opcode = memory.read_byte(self.reg.pc);
             cycles += self.run_opcode(opcode, memory, io, false);
         }
@@ -62,11 +136,71 @@ impl Z80 {
         cycles
     }
 
+    // accepts a pending NMI or maskable interrupt at an instruction
+    // boundary. returns the T-states taken if one was accepted
+    fn handle_interrupts(&mut self, memory: &mut Memory, io: &mut InputOutput) -> Option<usize> {
+        if self.nmi {
+            self.nmi = false;
+            self.halted = false;
+            // IFF2 remembers whether interrupts were enabled for RETN
+            self.iff1 = false;
+            self.refresh();
+
+            let pc = self.reg.pc;
+            self.push_stack_16(memory, pc);
+            self.reg.pc = 0x0066;
+            self.reg.wz = self.reg.pc;
+            return Some(timing::NMI);
+        }
+
+        if !self.interrupt || !self.iff1 || self.ei_delay {
+            return None;
+        }
+
+        self.interrupt = false;
+        self.halted = false;
+        self.iff1 = false;
+        self.iff2 = false;
+
+        let cycles = match self.interrupt_mode {
+            // the opcode on the data bus (normally RST) is executed without
+            // advancing pc past it
+            0 => {
+                let opcode = self.interrupt_data;
+                self.reg.pc = self.reg.pc.wrapping_sub(1);
+                self.run_opcode(opcode, memory, io, false) + timing::IM0_ACKNOWLEDGE
+            }
+            // RST 38h
+            1 => {
+                self.refresh();
+                let pc = self.reg.pc;
+                self.push_stack_16(memory, pc);
+                self.reg.pc = 0x0038;
+                self.reg.wz = self.reg.pc;
+                timing::IM1
+            }
+            // jump through the vector table at I * 256 + data
+            _ => {
+                self.refresh();
+                let addr = memory.read_word(((self.reg.i as u16) << 8) | self.interrupt_data as u16);
+                let pc = self.reg.pc;
+                self.push_stack_16(memory, pc);
+                self.reg.pc = addr;
+                self.reg.wz = addr;
+                timing::IM2
+            }
+        };
+
+        Some(cycles)
+    }
+
     // mostly for debugging purposes
     pub fn get_pc(&mut self) -> u16 {
         return self.reg.pc;
     }
 
+    // runs an opcode and returns the number of T-states it took. ext is
+    // true if the opcode follows a DD or FD prefix
     fn run_opcode(
         &mut self,
         opcode: u8,
@@ -74,25 +208,27 @@ impl Z80 {
         io: &mut InputOutput,
         ext: bool,
     ) -> usize {
-        if self.interrupts_enabled {
-            if self.interrupt {
-                match self.interrupt_mode {
-                    _ => {
-                        self.interrupt = false;
-                        let addr = memory
-                            .read_word(((self.reg.i as u16) << 8) | self.interrupt_data as u16);
-
-                        // push pc onto stack
-                        let pc = self.reg.pc;
-                        self.push_stack_16(memory, pc);
-                        self.reg.pc = addr;
-                    }
-                }
-            }
+        self.refresh();
+        let mut cycles = if ext {
+            timing::XY[opcode as usize]
+        } else {
+            timing::MAIN[opcode as usize]
+        } as usize;
+
+        // (HL) becomes (IX+d) or (IY+d) after a prefix, with the displacement
+        // right after the opcode. H and L keep their meaning in these opcodes
+        self.operand_addr = self.reg.hl();
+        if ext && Z80::indexed(opcode) {
+            let d = memory.read_byte(self.reg.pc.wrapping_add(1)) as i8;
+            self.operand_addr = self.operand_addr.wrapping_add(d as u16);
+            self.reg.wz = self.operand_addr;
+            self.reg.patch_ix(false);
+            self.reg.patch_iy(false);
+            self.inc_pc();
         }
 
-        let n: u8 = memory.read_byte(self.reg.pc + 1);
-        let nn: u16 = memory.read_word(self.reg.pc + 1);
+        let n: u8 = memory.read_byte(self.reg.pc.wrapping_add(1));
+        let nn: u16 = memory.read_word(self.reg.pc.wrapping_add(1));
         let d: i8 = n as i8;
 
         let x: u8 = opcode >> 6;
@@ -103,42 +239,36 @@ impl Z80 {
             // NOP
             (0, 0, 0) => {
                 self.inc_pc();
-                4
             }
             // EX AF, AF'
             (0, 1, 0) => {
                 let temp = self.altreg.af();
                 self.altreg.write_af(self.reg.af());
                 self.reg.write_af(temp);
-                4
+                self.inc_pc();
             }
             // DJNZ d
             (0, 2, 0) => {
-                if self.reg.b > 0 {
-                    self.reg.b -= 1;
-                }
-                if self.reg.b > 0 {
+                self.reg.b = self.reg.b.wrapping_sub(1);
+                if self.reg.b != 0 {
                     self.jr(d);
-                    13
+                    cycles += timing::JR_TAKEN;
                 } else {
                     self.reg.pc += 2;
-                    8
                 }
             }
             // JR d
             (0, 3, 0) => {
                 self.jr(d);
-                12
             }
             // JR cc[y-4], d
             (0, 4...7, 0) => {
                 if self.reg.cc((y - 4) as usize) {
                     self.jr(d);
-                    return 12;
+                    cycles += timing::JR_TAKEN;
                 } else {
                     self.reg.pc += 2;
                 }
-                7
             }
             (0, _, 1) => {
                 let q = (y & 1) != 0;
@@ -146,17 +276,14 @@ impl Z80 {
 
                 // ADD HL, rp[p]
                 if q {
-                    let result = self.reg.hl().wrapping_add(self.reg.read_16bit_r(p, true));
-                    self.reg.write_hl(result);
+                    let val = self.reg.read_16bit_r(p, true);
+                    self.add_hl(val);
                     self.inc_pc();
-                    11
                 }
                 // LD rp[p], nn
                 else {
-                    println!("{:x}, {:x}", self.reg.pc, nn);
                     self.reg.write_16bit_r(p, true, nn);
                     self.reg.pc += 3;
-                    10
                 }
             }
             (0, _, 2) => {
@@ -168,61 +295,55 @@ impl Z80 {
                         // LD A, (BC)
                         0 => {
                             self.reg.a = memory.read_byte(self.reg.bc());
+                            self.reg.wz = self.reg.bc().wrapping_add(1);
                             self.inc_pc();
-                            7
                         }
                         // LD A, (DE)
                         1 => {
                             self.reg.a = memory.read_byte(self.reg.de());
+                            self.reg.wz = self.reg.de().wrapping_add(1);
                             self.inc_pc();
-                            7
                         }
                         // LD HL, (nn)
                         2 => {
                             self.reg.write_hl(memory.read_word(nn));
+                            self.reg.wz = nn.wrapping_add(1);
                             self.reg.pc += 3;
-                            16
                         }
                         // LD A, (nn)
-                        3 => {
+                        _ => {
                             self.reg.a = memory.read_byte(nn);
+                            self.reg.wz = nn.wrapping_add(1);
                             self.reg.pc += 3;
-                            13
-                        }
-                        _ => {
-                            self.inc_pc();
-                            4
                         }
                     }
                 } else {
                     match p {
                         // LD (BC), A
                         0 => {
-                            memory.write_byte(self.reg.a, self.reg.bc());
+                            let addr = self.reg.bc();
+                            memory.write_byte(self.reg.a, addr);
+                            self.store_a_wz(addr);
                             self.inc_pc();
-                            7
                         }
                         // LD (DE), A
                         1 => {
-                            memory.write_byte(self.reg.a, self.reg.de());
+                            let addr = self.reg.de();
+                            memory.write_byte(self.reg.a, addr);
+                            self.store_a_wz(addr);
                             self.inc_pc();
-                            7
                         }
                         // LD (nn), HL
                         2 => {
                             memory.write_word(self.reg.hl(), nn);
+                            self.reg.wz = nn.wrapping_add(1);
                             self.reg.pc += 3;
-                            16
                         }
                         // LD (nn), A
-                        3 => {
+                        _ => {
                             memory.write_byte(self.reg.a, nn);
+                            self.store_a_wz(nn);
                             self.reg.pc += 3;
-                            13
-                        }
-                        _ => {
-                            self.inc_pc();
-                            4
                         }
                     }
                 }
@@ -234,71 +355,45 @@ impl Z80 {
                 // DEC rp[p]
                 if q {
                     self.dec_16(p);
-                    6
                 }
                 // INC rp[p]
                 else {
                     self.inc_16(p);
-                    6
                 }
             }
             // INC r[y]
             (0, _, 4) => {
                 self.inc_8(y, memory);
-                4
             }
             // DEC r[y]
             (0, _, 5) => {
                 self.dec_8(y, memory);
-                4
             }
             // LD r[y], n
             (0, _, 6) => {
                 self.write_r(y, n, memory);
                 self.reg.pc += 2;
-                7
             }
             // RLCA
             (0, 0, 7) => {
-                let val = self.reg.a;
-                self.reg.a = self.rot(0, val);
-                self.inc_pc();
-                4
+                self.rot_a(0);
             }
             // RRCA
             (0, 1, 7) => {
-                let val = self.reg.a;
-                self.reg.a = self.rot(1, val);
-                self.inc_pc();
-                4
+                self.rot_a(1);
             }
             // RLA
             (0, 2, 7) => {
-                let val = self.reg.a;
-                self.reg.a = self.rot(2, val);
-                self.inc_pc();
-                4
+                self.rot_a(2);
             }
             // RRA
             (0, 3, 7) => {
-                let val = self.reg.a;
-                self.reg.a = self.rot(3, val);
-                self.inc_pc();
-                4
+                self.rot_a(3);
             }
             // DAA
             (0, 4, 7) => {
-                if self.reg.a & 0xF0 > 9 || self.reg.read_flag(4) {
-                    self.reg.a += 6;
-                }
-                if (self.reg.a & 0xF0) >> 4 > 9 {
-                    self.reg.a += 0x60;
-                    self.reg.set_flag(0, true);
-                }
-                let num = self.reg.a;
-                self.detect_parity(num);
+                self.daa();
                 self.inc_pc();
-                4
             }
             // CPL
             (0, 5, 7) => {
@@ -307,7 +402,8 @@ impl Z80 {
                 // set H and N
                 self.reg.set_flag(1, true);
                 self.reg.set_flag(4, true);
-                4
+                let a = self.reg.a;
+                self.set_undocumented(a);
             }
             // SCF
             (0, 6, 7) => {
@@ -316,7 +412,8 @@ impl Z80 {
                 // reset H and N
                 self.reg.set_flag(1, false);
                 self.reg.set_flag(4, false);
-                4
+                let a = self.reg.a;
+                self.set_undocumented(a);
             }
             // CCF
             (0, 7, 7) => {
@@ -328,36 +425,33 @@ impl Z80 {
                 // inverse C
                 let inverse_c = !self.reg.read_flag(0);
                 self.reg.set_flag(0, inverse_c);
+                let a = self.reg.a;
+                self.set_undocumented(a);
                 self.inc_pc();
-                4
             }
             // HALT
             (1, 6, 6) => {
                 self.halted = true;
                 self.inc_pc();
-                4
             }
             // LD r[y], r[z]
             (1, _, _) => {
                 let temp = self.r(z, memory);
                 self.write_r(y, temp, memory);
                 self.inc_pc();
-                4
             }
             // alu[y] r[z]
             (2, _, _) => {
                 let val = self.r(z, memory);
                 self.alu(y, val);
-                4
             }
             // RET cc[y]
             (3, _, 0) => {
                 if self.reg.cc(y as usize) {
                     self.ret(memory);
-                    11
+                    cycles += timing::RET_TAKEN;
                 } else {
                     self.inc_pc();
-                    5
                 }
             }
             (3, _, 1) => {
@@ -370,12 +464,10 @@ impl Z80 {
                         let word = self.pop_stack_16(memory);
                         self.reg.write_16bit_r(p, false, word);
                         self.inc_pc();
-                        10
                     }
                     // RET
                     (0, 1) => {
                         self.ret(memory);
-                        11
                     }
                     // EXX
                     (1, _) => {
@@ -389,62 +481,56 @@ impl Z80 {
                         self.altreg.write_hl(self.reg.hl());
                         self.reg.write_hl(temp);
                         self.inc_pc();
-                        4
                     }
                     // JP HL
                     (2, _) => {
                         self.reg.pc = self.reg.hl();
-                        4
                     }
                     // LD SP, HL
-                    (3, _) => {
+                    (_, _) => {
                         self.reg.sp = self.reg.hl();
                         self.inc_pc();
-                        4
                     }
-                    (_, _) => 4,
                 }
             }
             // JP cc[y], nn
             (3, _, 2) => {
+                self.reg.wz = nn;
                 if self.reg.cc(y as usize) {
                     self.reg.pc = nn;
+                } else {
+                    self.reg.pc += 3;
                 }
-                10
             }
             // JP nn
             (3, 0, 3) => {
                 self.reg.pc = nn;
-                10
+                self.reg.wz = nn;
             }
             // CB prefix
             (3, 1, 3) => {
                 self.inc_pc();
-                self.run_cb_opcode(memory, io, ext);
-                4
+                cycles = self.run_cb_opcode(memory, io, ext);
             }
             // OUT (n), A
             (3, 2, 3) => {
                 io.output(n, self.reg.a);
-                self.inc_pc();
-                4
+                self.store_a_wz(n as u16);
+                self.reg.pc += 2;
             }
             // IN A, (n)
             (3, 3, 3) => {
+                self.reg.wz = ((self.reg.a as u16) << 8 | n as u16).wrapping_add(1);
                 self.reg.a = io.input(n);
-                self.inc_pc();
-                4
+                self.reg.pc += 2;
             }
             // EX (SP), HL
             (3, 4, 3) => {
-                let mut temp = self.reg.l;
-                self.reg.l = memory.read_byte(self.reg.sp);
-                memory.write_byte(temp, self.reg.sp);
-                temp = self.reg.h;
-                self.reg.h = memory.read_byte(self.reg.sp + 1);
-                memory.write_byte(temp, self.reg.sp + 1);
+                let temp = self.reg.hl();
+                self.reg.wz = memory.read_word(self.reg.sp);
+                self.reg.write_hl(self.reg.wz);
+                memory.write_word(temp, self.reg.sp);
                 self.inc_pc();
-                19
             }
             // EX DE, HL
             (3, 5, 3) => {
@@ -453,27 +539,28 @@ impl Z80 {
                 self.reg.write_de(hl);
                 self.reg.write_hl(de);
                 self.inc_pc();
-                4
             }
             // DI
             (3, 6, 3) => {
-                self.interrupts_enabled = false;
+                self.iff1 = false;
+                self.iff2 = false;
                 self.inc_pc();
-                4
             }
             // EI
             (3, 7, 3) => {
-                self.interrupts_enabled = true;
+                self.iff1 = true;
+                self.iff2 = true;
+                self.ei_delay = true;
                 self.inc_pc();
-                4
             }
             // CALL cc[y], nn
             (3, _, 4) => {
+                self.reg.wz = nn;
                 if self.reg.cc(y as usize) {
                     self.call(memory, nn);
-                    17
+                    cycles += timing::CALL_TAKEN;
                 } else {
-                    10
+                    self.reg.pc += 3;
                 }
             }
             (3, _, 5) => {
@@ -486,63 +573,77 @@ impl Z80 {
                         let word = self.reg.read_16bit_r(p, false);
                         self.push_stack_16(memory, word);
                         self.inc_pc();
-                        11
                     }
                     // CALL nn
                     (0, 1) => {
                         self.call(memory, nn);
-                        17
                     }
                     // DD prefix
                     (1, _) => {
                         self.inc_pc();
                         let opcode = memory.read_byte(self.reg.pc);
-                        self.reg.patch_ix(true);
-                        let cycles = self.run_opcode(opcode, memory, io, true);
-                        self.reg.patch_ix(false);
-                        cycles
+                        if Z80::is_prefix(opcode) {
+                            cycles = timing::IGNORED_PREFIX;
+                        } else {
+                            self.reg.patch_ix(true);
+                            cycles = self.run_opcode(opcode, memory, io, true);
+                            self.reg.patch_ix(false);
+                        }
                     }
                     // ED prefix
                     (2, _) => {
                         self.inc_pc();
                         let op = memory.read_byte(self.reg.pc);
-                        self.run_ed_opcode(op, memory, io);
-                        4
+                        cycles = self.run_ed_opcode(op, memory, io);
                     }
                     // FD prefix
-                    (3, _) => {
+                    (_, _) => {
                         self.inc_pc();
                         let opcode = memory.read_byte(self.reg.pc);
-                        self.reg.patch_iy(true);
-                        let cycles = self.run_opcode(opcode, memory, io, true);
-                        self.reg.patch_iy(false);
-                        cycles
+                        if Z80::is_prefix(opcode) {
+                            cycles = timing::IGNORED_PREFIX;
+                        } else {
+                            self.reg.patch_iy(true);
+                            cycles = self.run_opcode(opcode, memory, io, true);
+                            self.reg.patch_iy(false);
+                        }
                     }
-                    (_, _) => 4,
                 }
             }
             // alu[y] n
             (3, _, 6) => {
                 self.alu(y, n);
-                8
+                self.inc_pc();
             }
             // RST y*8
             (3, _, 7) => {
-                self.call(memory, (y * 8) as u16);
-                11
+                let ret_addr = self.reg.pc.wrapping_add(1);
+                self.push_stack_16(memory, ret_addr);
+                self.reg.pc = (y * 8) as u16;
+                self.reg.wz = self.reg.pc;
             }
-            (_, _, _) => 4,
+            (_, _, _) => {}
         }
+
+        cycles
     }
 
-    // runs a CB prefixed opcode
+    // runs a CB prefixed opcode. after a DD or FD prefix the displacement
+    // comes before the opcode, the operand is always (IX+d) or (IY+d) and
+    // the result is also copied into r[z] unless z is 6
     fn run_cb_opcode(&mut self, memory: &mut Memory, io: &mut InputOutput, ext: bool) -> usize {
-        let mut d: u16 = 0;
+        let mut addr = self.reg.hl();
         if ext {
-            // cast to u16 because addresses are 16 bit
-            d = memory.read_byte(self.reg.pc) as u16;
+            let d = memory.read_byte(self.reg.pc) as i8;
+            addr = addr.wrapping_add(d as u16);
+            self.reg.wz = addr;
             // account for displacement byte
             self.inc_pc();
+            // the undocumented copies go to H and L, not IXH and IXL
+            self.reg.patch_ix(false);
+            self.reg.patch_iy(false);
+        } else {
+            self.refresh();
         }
 
         let opcode = memory.read_byte(self.reg.pc);
@@ -550,124 +651,95 @@ impl Z80 {
         let y: u8 = (opcode & 0b00111000) >> 3;
         let z: u8 = opcode & 0b00000111;
 
-        match x {
+        let in_memory = ext || z == 6;
+        let val = if in_memory {
+            memory.read_byte(addr)
+        } else {
+            self.reg.read_8bit_r(z)
+        };
+
+        let result = match x {
             // rot[y] r[z]
-            0 => {
-                if z == 6 {
-                    let val = memory.read_byte(self.reg.hl() + d);
-                    let result = self.rot(y, val);
-                    memory.write_byte(result, self.reg.hl() + d);
-                } else if ext {
-                    let val = memory.read_byte(self.reg.hl() + d);
-                    let result = self.rot(y, val);
-                    memory.write_byte(result, self.reg.hl() + d);
-                    self.write_r(z, result, memory);
-                } else {
-                    let val = self.r(z, memory);
-                    let result = self.rot(y, val);
-                    self.write_r(z, result, memory);
-                }
-                self.inc_pc();
-                4
-            }
+            0 => Some(self.rot(y, val)),
             // BIT y, r[z]
             1 => {
-                // (IX + d) if ext, r[z] otherwise
-                let val = if ext {
-                    memory.read_byte(self.reg.hl() + d)
-                } else {
-                    self.r(z, memory)
-                };
-                self.reg.set_flag(6, (val & 1 << y) != 0);
+                let set = (val & 1 << y) != 0;
                 self.reg.set_flag(1, false);
+                self.reg.set_flag(2, !set);
                 self.reg.set_flag(4, true);
-                self.inc_pc();
-                8
-            }
-            // RES y, r[z]
-            2 => {
-                if z == 6 {
-                    let val = memory.read_byte(self.reg.hl() + d);
-                    memory.write_byte(val & !(1 << y), self.reg.hl() + d);
-                } else if ext {
-                    let val = memory.read_byte(self.reg.hl() + d);
-                    let result = val & !(1 << y);
-                    memory.write_byte(result, self.reg.hl() + d);
-                    self.write_r(z, result, memory);
+                self.reg.set_flag(6, !set);
+                self.reg.set_flag(7, set && y == 7);
+                // with a memory operand bits 3 and 5 leak from MEMPTR
+                if in_memory {
+                    let wz = (self.reg.wz >> 8) as u8;
+                    self.set_undocumented(wz);
                 } else {
-                    let val = self.r(z, memory);
-                    self.write_r(z, val & !(1 << y), memory);
+                    self.set_undocumented(val);
                 }
-                self.inc_pc();
-                8
+                None
             }
+            // RES y, r[z]
+            2 => Some(val & !(1 << y)),
             // SET y, r[z]
-            3 => {
-                if z == 6 {
-                    let val = memory.read_byte(self.reg.hl() + d);
-                    memory.write_byte(val | (1 << y), self.reg.hl() + d);
-                } else if ext {
-                    let val = memory.read_byte(self.reg.hl() + d);
-                    let result = val | (1 << y);
-                    memory.write_byte(result, self.reg.hl() + d);
-                    self.write_r(z, result, memory);
-                } else {
-                    let val = self.r(z, memory);
-                    self.write_r(z, val | (1 << y), memory);
-                }
-                self.inc_pc();
-                8
+            _ => Some(val | (1 << y)),
+        };
+
+        if let Some(result) = result {
+            if in_memory {
+                memory.write_byte(result, addr);
+            }
+            if z != 6 {
+                self.reg.write_8bit_r(z, result);
             }
-            _ => 4,
+        }
+        self.inc_pc();
+
+        if ext {
+            timing::XYCB[opcode as usize] as usize
+        } else {
+            timing::CB[opcode as usize] as usize
         }
     }
 
     // runs an ED prefixed opcode
     fn run_ed_opcode(&mut self, opcode: u8, memory: &mut Memory, io: &mut InputOutput) -> usize {
-        let nn: u16 = memory.read_word(self.reg.pc + 1);
+        self.refresh();
+        let mut cycles = timing::ED[opcode as usize] as usize;
+        let nn: u16 = memory.read_word(self.reg.pc.wrapping_add(1));
 
         let x: u8 = opcode >> 6;
         let y: u8 = (opcode & 0b00111000) >> 3;
         let z: u8 = opcode & 0b00000111;
 
         match (x, y, z) {
-            // IN (C)
-            (1, 6, 0) => {
-                let val = io.input(self.reg.c);
-
-                self.detect_parity(val);
-                self.reg.set_flag(1, false);
-                self.reg.set_flag(4, false);
-                self.reg.set_flag(6, val == 0);
-                self.reg.set_flag(7, val > 127);
-                self.inc_pc();
-                12
-            }
-            // IN r[y], (C)
+            // IN (C), IN r[y], (C)
             (1, _, 0) => {
                 let val = io.input(self.reg.c);
+                self.reg.wz = self.reg.bc().wrapping_add(1);
 
                 self.detect_parity(val);
                 self.reg.set_flag(1, false);
                 self.reg.set_flag(4, false);
-                self.reg.set_flag(6, val == 0);
-                self.reg.set_flag(7, val > 127);
+                self.set_sign_zero(val);
+                self.set_undocumented(val);
                 self.inc_pc();
-                self.reg.write_8bit_r(y, val);
-                12
+                // IN (C) only sets the flags
+                if y != 6 {
+                    self.reg.write_8bit_r(y, val);
+                }
             }
             // OUT (C), 0
             (1, 6, 1) => {
                 io.output(self.reg.c, 0);
+                self.reg.wz = self.reg.bc().wrapping_add(1);
                 self.inc_pc();
-                12
             }
             // OUT (C), r[y]
             (1, _, 1) => {
                 let val = self.reg.read_8bit_r(y);
                 io.output(self.reg.c, val);
+                self.reg.wz = self.reg.bc().wrapping_add(1);
                 self.inc_pc();
-                12
             }
             (1, _, 2) => {
                 let q = (y & 1) != 0;
@@ -678,13 +750,11 @@ impl Z80 {
                 if q {
                     self.add_16(value, true);
                     self.inc_pc();
-                    15
                 }
                 // SBC HL, rp[p]
                 else {
                     self.sub_16(value, true);
                     self.inc_pc();
-                    15
                 }
             }
             (1, _, 3) => {
@@ -695,103 +765,91 @@ impl Z80 {
                 if q {
                     let val = memory.read_word(nn);
                     self.reg.write_16bit_r(p, true, val);
-                    self.inc_pc();
-                    20
+                    self.reg.pc += 3;
                 }
                 // LD (nn), rp[p]
                 else {
                     let val = self.reg.read_16bit_r(p, true);
                     memory.write_word(val, nn);
-                    self.inc_pc();
-                    20
+                    self.reg.pc += 3;
                 }
+                self.reg.wz = nn.wrapping_add(1);
             }
             // NEG
             (1, _, 4) => {
-                let neg: i8 = 0;
                 let a = self.reg.a;
-                self.detect_overflow_sub(0, a, false);
-                self.detect_half_carry_sub(0, a, false);
-                let val = neg.wrapping_sub(a as i8) as u8;
-                self.reg.a = val;
-                self.reg.set_flag(0, false);
-                self.reg.set_flag(1, true);
-                self.reg.set_flag(6, val == 0);
-                self.reg.set_flag(7, val > 127);
-                8
-            }
-            // TODO i think these are supposed to do something other than just returning
-            // RETI
-            (1, 1, 5) => {
-                self.ret(memory);
-                14
+                self.reg.a = 0;
+                self.sub(a, false);
+                self.inc_pc();
             }
-            // RETN
+            // RETN, and RETI which only differs in how the interrupting
+            // device sees it
             (1, _, 5) => {
+                self.iff1 = self.iff2;
                 self.ret(memory);
-                14
             }
             // IM
             (1, _, 6) => {
                 self.interrupt_mode = match y {
-                    0 | 4 => 0,
-                    1 | 5 | 2 | 6 => 1,
+                    0 | 1 | 4 | 5 => 0,
+                    2 | 6 => 1,
                     _ => 2,
                 };
                 self.inc_pc();
-                8
             }
             // LD I, A
             (1, 0, 7) => {
                 self.reg.i = self.reg.a;
                 self.inc_pc();
-                println!("{:x}", self.reg.i);
-                9
             }
             // LD R, A
             (1, 1, 7) => {
                 self.reg.r = self.reg.a;
                 self.inc_pc();
-                9
             }
             // LD A, I
             (1, 2, 7) => {
-                self.reg.a = self.reg.i;
-                self.inc_pc();
-                9
+                let val = self.reg.i;
+                self.load_interrupt_register(val);
             }
             // LD A, R
             (1, 3, 7) => {
-                self.reg.a = self.reg.r;
-                self.inc_pc();
-                9
+                let val = self.reg.r;
+                self.load_interrupt_register(val);
             }
             // RRD
             (1, 4, 7) => {
-                // i have no clue if this is even used in pacman so f it
-                // TODO maybe implement in the future if i plan to reuse this z80 code
-                println!("RRD");
-                18
+                self.rotate_digit(memory, true);
+                self.inc_pc();
             }
             // RLD
             (1, 5, 7) => {
-                // see above
-                println!("RLD");
-                18
+                self.rotate_digit(memory, false);
+                self.inc_pc();
+            }
+            (2, 4...7, 0...3) => {
+                if self.bli(y, z, memory, io) {
+                    // point back at the ED prefix so the instruction runs again
+                    self.reg.pc -= 1;
+                    cycles += timing::BLOCK_REPEAT;
+                } else {
+                    self.inc_pc();
+                }
             }
-            (2, _, 0...3) => {
-                self.bli(y, z, memory, io);
-                16
+            // anything else acts as two NOPs
+            (_, _, _) => {
+                self.inc_pc();
             }
-            (_, _, _) => 4,
         }
+
+        cycles
     }
 
     // implements the r table in the decoding opcodes documentation with (hl)
     // at 6
     fn r(&mut self, index: u8, mem: &mut Memory) -> u8 {
         if index == 6 {
-            mem.read_byte(self.reg.hl())
+            mem.read_byte(self.operand_addr)
         } else {
             self.reg.read_8bit_r(index)
         }
@@ -799,151 +857,75 @@ impl Z80 {
 
     fn write_r(&mut self, index: u8, byte: u8, mem: &mut Memory) {
         if index == 6 {
-            mem.write_byte(byte, self.reg.hl());
+            mem.write_byte(byte, self.operand_addr);
         } else {
             self.reg.write_8bit_r(index, byte);
         }
     }
 
-    fn bli(&mut self, a: u8, b: u8, mem: &mut Memory, io: &mut InputOutput) {
-        match (a, b) {
-            // LDI
-            (4, 0) => {
-                self.load_inc_dec(mem, true);
-            }
-            // LDD
-            (5, 0) => {
-                self.load_inc_dec(mem, false);
-            }
-            // LDIR
-            (6, 0) => {
-                self.load_inc_dec(mem, true);
-                let bc = self.reg.bc();
-                // repeat if BC is not 0
-                if bc != 0 {
-                    self.load_inc_dec(mem, true);
-                }
-            }
-            // LDDR
-            (7, 0) => {
-                self.load_inc_dec(mem, false);
-                let bc = self.reg.bc();
-                // repeat if BC is not 0
-                if bc != 0 {
-                    self.load_inc_dec(mem, false);
-                }
-            }
-            // CPI
-            (4, 1) => {
-                self.comp_inc_dec(mem, true);
-            }
-            // CPD
-            (5, 1) => {
-                self.comp_inc_dec(mem, false);
-            }
-            // CPIR
-            (6, 1) => {
-                self.comp_inc_dec(mem, true);
-                let bc = self.reg.bc();
-                // repeat if BC is not 0
-                if bc != 0 {
-                    self.load_inc_dec(mem, false);
-                }
+    // MEMPTR after storing A: the address plus one in the low byte and A in
+    // the high byte
+    fn store_a_wz(&mut self, addr: u16) {
+        self.reg.wz = (self.reg.a as u16) << 8 | (addr.wrapping_add(1) & 0xFF);
+    }
+
+    // used by LD A, I and LD A, R, which copy IFF2 into P/V
+    fn load_interrupt_register(&mut self, val: u8) {
+        self.reg.a = val;
+        let iff2 = self.iff2;
+        self.reg.set_flag(1, false);
+        self.reg.set_flag(2, iff2);
+        self.reg.set_flag(4, false);
+        self.set_sign_zero(val);
+        self.set_undocumented(val);
+        self.inc_pc();
+    }
+
+    // runs block instruction bli[a, b] once. returns true if it is a
+    // repeating instruction that has to run again
+    fn bli(&mut self, a: u8, b: u8, mem: &mut Memory, io: &mut InputOutput) -> bool {
+        // LDI, CPI, INI, OUTI and their repeating versions increment,
+        // the rest decrement
+        let inc = a & 1 == 0;
+        let repeating = a >= 6;
+
+        let again = match b {
+            // LDI, LDD, LDIR, LDDR
+            0 => {
+                self.load_inc_dec(mem, inc);
+                self.reg.bc() != 0
             }
-            // CPDIR
-            (7, 1) => {
-                self.comp_inc_dec(mem, false);
-                let bc = self.reg.bc();
-                // repeat if BC is not 0
-                if bc != 0 {
-                    self.load_inc_dec(mem, false);
-                }
+            // CPI, CPD, CPIR, CPDR
+            1 => {
+                self.comp_inc_dec(mem, inc);
+                self.reg.bc() != 0 && !self.reg.read_flag(6)
             }
-            // INI
-            (4, 2) => {
-                mem.write_byte(io.input(self.reg.c), self.reg.hl());
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp + 1);
-                self.reg.b -= 1;
-            }
-            // IND
-            (5, 2) => {
-                mem.write_byte(io.input(self.reg.c), self.reg.hl());
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp - 1);
-                self.reg.b -= 1;
-            }
-            // INIR
-            (6, 2) => {
-                mem.write_byte(io.input(self.reg.c), self.reg.hl());
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp + 1);
-                self.reg.b -= 1;
-
-                if self.reg.b != 0 {
-                    mem.write_byte(io.input(self.reg.c), self.reg.hl());
-                    let tmp = self.reg.hl();
-                    self.reg.write_hl(tmp + 1);
-                    self.reg.b -= 1;
-                }
+            // INI, IND, INIR, INDR
+            2 => {
+                self.in_inc_dec(mem, io, inc);
+                self.reg.b != 0
             }
-            // INDR
-            (7, 2) => {
-                mem.write_byte(io.input(self.reg.c), self.reg.hl());
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp - 1);
-                self.reg.b -= 1;
-
-                if self.reg.b != 0 {
-                    mem.write_byte(io.input(self.reg.c), self.reg.hl());
-                    let tmp = self.reg.hl();
-                    self.reg.write_hl(tmp - 1);
-                    self.reg.b -= 1;
-                }
+            // OUTI, OUTD, OTIR, OTDR
+            _ => {
+                self.out_inc_dec(mem, io, inc);
+                self.reg.b != 0
             }
-            // OUTI
-            (4, 3) => {
-                io.output(self.reg.c, mem.read_byte(self.reg.hl()));
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp + 1);
-                self.reg.b -= 1;
-            }
-            // OUTD
-            (5, 2) => {
-                io.output(self.reg.c, mem.read_byte(self.reg.hl()));
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp - 1);
-                self.reg.b -= 1;
-            }
-            // OUTIR
-            (6, 2) => {
-                io.output(self.reg.c, mem.read_byte(self.reg.hl()));
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp + 1);
-                self.reg.b -= 1;
+        };
 
-                if self.reg.b != 0 {
-                    io.output(self.reg.c, mem.read_byte(self.reg.hl()));
-                    let tmp = self.reg.hl();
-                    self.reg.write_hl(tmp + 1);
-                    self.reg.b -= 1;
-                }
-            }
-            // OUTDR
-            (7, 2) => {
-                io.output(self.reg.c, mem.read_byte(self.reg.hl()));
-                let tmp = self.reg.hl();
-                self.reg.write_hl(tmp - 1);
-                self.reg.b -= 1;
+        if repeating && again {
+            // pc is still on the opcode here
+            self.reg.wz = self.reg.pc;
+        }
+        repeating && again
+    }
 
-                if self.reg.b != 0 {
-                    io.output(self.reg.c, mem.read_byte(self.reg.hl()));
-                    let tmp = self.reg.hl();
-                    self.reg.write_hl(tmp - 1);
-                    self.reg.b -= 1;
-                }
-            }
-            (_, _) => {}
+    // increments or decrements HL based on <inc>
+    fn step_hl(&mut self, inc: bool) {
+        let hl = self.reg.hl();
+        if inc {
+            self.reg.write_hl(hl.wrapping_add(1));
+        } else {
+            self.reg.write_hl(hl.wrapping_sub(1));
         }
     }
 
@@ -953,20 +935,23 @@ impl Z80 {
         let byte = mem.read_byte(self.reg.hl());
         mem.write_byte(byte, self.reg.de());
 
-        let mut tmp = self.reg.de();
+        let de = self.reg.de();
         if inc {
-            // increment HL and DE
-            self.reg.write_de(tmp + 1);
-            tmp = self.reg.hl();
-            self.reg.write_hl(tmp + 1);
+            self.reg.write_de(de.wrapping_add(1));
         } else {
-            // decrement HL and DE
-            self.reg.write_de(tmp - 1);
-            tmp = self.reg.hl();
-            self.reg.write_hl(tmp - 1);
+            self.reg.write_de(de.wrapping_sub(1));
         }
-        tmp = self.reg.bc();
-        self.reg.write_bc(tmp - 1);
+        self.step_hl(inc);
+        let bc = self.reg.bc().wrapping_sub(1);
+        self.reg.write_bc(bc);
+
+        // bits 3 and 5 come from bits 3 and 1 of the byte plus A
+        let n = byte.wrapping_add(self.reg.a);
+        self.reg.set_flag(1, false);
+        self.reg.set_flag(2, bc != 0);
+        self.reg.set_flag(3, n & 1 << 3 != 0);
+        self.reg.set_flag(4, false);
+        self.reg.set_flag(5, n & 1 << 1 != 0);
     }
 
     // Compares (HL) and A and increments or decrements HL based on <inc>
@@ -974,22 +959,99 @@ impl Z80 {
     fn comp_inc_dec(&mut self, mem: &mut Memory, inc: bool) {
         let byte = mem.read_byte(self.reg.hl());
         let acc = self.reg.a;
-        let result = byte - acc;
+        let result = acc.wrapping_sub(byte);
+        self.detect_half_carry_sub(acc, byte, false);
+        self.set_sign_zero(result);
         self.reg.set_flag(1, true);
-        self.detect_half_carry_sub(byte, acc, false);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
 
-        let mut tmp = self.reg.hl();
-        if inc {
-            // increment HL
-            self.reg.write_hl(tmp + 1);
+        self.step_hl(inc);
+        let bc = self.reg.bc().wrapping_sub(1);
+        self.reg.write_bc(bc);
+        self.reg.set_flag(2, bc != 0);
+        self.reg.wz = if inc {
+            self.reg.wz.wrapping_add(1)
+        } else {
+            self.reg.wz.wrapping_sub(1)
+        };
+
+        // bits 3 and 5 come from bits 3 and 1 of the result minus H
+        let n = result.wrapping_sub(self.reg.read_flag(4) as u8);
+        self.reg.set_flag(3, n & 1 << 3 != 0);
+        self.reg.set_flag(5, n & 1 << 1 != 0);
+    }
+
+    // Reads a byte from port C into (HL) and increments or decrements HL
+    // based on <inc>. B is always decremented
+    fn in_inc_dec(&mut self, mem: &mut Memory, io: &mut InputOutput, inc: bool) {
+        let byte = io.input(self.reg.c);
+        mem.write_byte(byte, self.reg.hl());
+        self.step_hl(inc);
+
+        let bc = self.reg.bc();
+        let c = if inc {
+            self.reg.wz = bc.wrapping_add(1);
+            self.reg.c.wrapping_add(1)
+        } else {
+            self.reg.wz = bc.wrapping_sub(1);
+            self.reg.c.wrapping_sub(1)
+        };
+        self.reg.b = self.reg.b.wrapping_sub(1);
+        self.block_io_flags(byte, c);
+    }
+
+    // Writes (HL) to port C and increments or decrements HL based on <inc>.
+    // B is always decremented, before the port is written to
+    fn out_inc_dec(&mut self, mem: &mut Memory, io: &mut InputOutput, inc: bool) {
+        let byte = mem.read_byte(self.reg.hl());
+        self.reg.b = self.reg.b.wrapping_sub(1);
+        io.output(self.reg.c, byte);
+        self.step_hl(inc);
+
+        let bc = self.reg.bc();
+        self.reg.wz = if inc {
+            bc.wrapping_add(1)
         } else {
-            // decrement HL
-            self.reg.write_hl(tmp - 1);
+            bc.wrapping_sub(1)
+        };
+        let l = self.reg.l;
+        self.block_io_flags(byte, l);
+    }
+
+    // sets the (mostly undocumented) flags of INI, IND, OUTI and OUTD, where
+    // <byte> is the transferred byte and <k> is what it gets added to
+    fn block_io_flags(&mut self, byte: u8, k: u8) {
+        let b = self.reg.b;
+        let sum = byte as u16 + k as u16;
+
+        self.set_sign_zero(b);
+        self.set_undocumented(b);
+        self.reg.set_flag(0, sum > 0xFF);
+        self.reg.set_flag(1, byte & 1 << 7 != 0);
+        self.reg.set_flag(4, sum > 0xFF);
+        self.detect_parity((sum as u8 & 0x07) ^ b);
+    }
+
+    // rotates the nibbles of A and (HL) right (RRD) or left (RLD)
+    fn rotate_digit(&mut self, mem: &mut Memory, right: bool) {
+        let hl = self.reg.hl();
+        let val = mem.read_byte(hl);
+        let a = self.reg.a;
+
+        if right {
+            mem.write_byte((a << 4) | (val >> 4), hl);
+            self.reg.a = (a & 0xF0) | (val & 0x0F);
+        } else {
+            mem.write_byte((val << 4) | (a & 0x0F), hl);
+            self.reg.a = (a & 0xF0) | (val >> 4);
         }
-        tmp = self.reg.bc();
-        self.reg.write_bc(tmp - 1);
+        self.reg.wz = hl.wrapping_add(1);
+
+        let result = self.reg.a;
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
+        self.detect_parity(result);
+        self.reg.set_flag(1, false);
+        self.reg.set_flag(4, false);
     }
 
     fn alu(&mut self, operator: u8, val: u8) {
@@ -1017,90 +1079,78 @@ impl Z80 {
 
     fn add(&mut self, val: u8, add_carry: bool) {
         let left = self.reg.a;
-        let right = val;
-        let mut result = right.wrapping_add(left);
-
-        if add_carry {
-            let carry = self.reg.read_flag(0);
-            result = result.wrapping_add(carry as u8);
-            self.detect_overflow_add(right, left, carry);
-            self.detect_half_carry_add(right, left, carry);
-        } else {
-            self.detect_overflow_add(right, left, false);
-            self.detect_half_carry_add(right, left, false);
-        }
+        let carry = add_carry && self.reg.read_flag(0);
+        let result = left.wrapping_add(val).wrapping_add(carry as u8);
+
+        self.detect_overflow_add(left, val, carry);
+        self.detect_half_carry_add(left, val, carry);
         self.reg.a = result;
 
-        self.reg.set_flag(0, (left + right) as u16 > 255);
+        self.reg
+            .set_flag(0, left as u16 + val as u16 + carry as u16 > 0xFF);
         self.reg.set_flag(1, false);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
     }
 
-    fn add_16(&mut self, val: u16, sub_carry: bool) {
+    // ADD HL, rr. S, Z and P/V are left alone
+    fn add_hl(&mut self, val: u16) {
         let left = self.reg.hl();
-        let right = val;
-        let mut result = left.wrapping_add(right);
-
-        if sub_carry {
-            let carry = self.reg.read_flag(0);
-            result = result.wrapping_add(carry as u16);
-            self.detect_overflow_add(right as u8, left as u8, carry);
-            self.detect_half_carry_add(right as u8, left as u8, carry);
-        } else {
-            self.detect_overflow_add(right as u8, left as u8, false);
-            self.detect_half_carry_add(right as u8, left as u8, false);
-        }
+        let result = left.wrapping_add(val);
+        self.reg.wz = left.wrapping_add(1);
         self.reg.write_hl(result);
 
-        self.reg.set_flag(0, (left + right) as u16 > 255);
+        self.reg.set_flag(0, left as u32 + val as u32 > 0xFFFF);
         self.reg.set_flag(1, false);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.reg.set_flag(4, (left & 0x0FFF) + (val & 0x0FFF) > 0x0FFF);
+        self.set_undocumented((result >> 8) as u8);
     }
 
-    fn sub(&mut self, val: u8, sub_carry: bool) {
-        let left = self.reg.a;
-        let right = val;
-        let mut result = left.wrapping_sub(right);
-
-        if sub_carry {
-            let carry = self.reg.read_flag(0);
-            result = result.wrapping_sub(carry as u8);
-            self.detect_overflow_sub(right, left, carry);
-            self.detect_half_carry_sub(right, left, carry);
-        } else {
-            self.detect_overflow_sub(right, left, false);
-            self.detect_half_carry_sub(right, left, false);
-        }
-        self.reg.a = result;
+    // ADC HL, rr
+    fn add_16(&mut self, val: u16, add_carry: bool) {
+        let left = self.reg.hl();
+        let carry = add_carry && self.reg.read_flag(0);
+        let result = left.wrapping_add(val).wrapping_add(carry as u16);
+        self.reg.wz = left.wrapping_add(1);
+        self.reg.write_hl(result);
 
-        self.reg.set_flag(0, (left + right) as u16 > 255);
-        self.reg.set_flag(1, true);
+        // the high bytes carry the same flags as an 8 bit addition
+        let low_carry = (left & 0xFF) + (val & 0xFF) + carry as u16 > 0xFF;
+        let (high_left, high_right) = ((left >> 8) as u8, (val >> 8) as u8);
+        self.detect_overflow_add(high_left, high_right, low_carry);
+        self.detect_half_carry_add(high_left, high_right, low_carry);
+
+        self.reg
+            .set_flag(0, left as u32 + val as u32 + carry as u32 > 0xFFFF);
+        self.reg.set_flag(1, false);
         self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.reg.set_flag(7, result & 0x8000 != 0);
+        self.set_undocumented((result >> 8) as u8);
     }
 
+    fn sub(&mut self, val: u8, sub_carry: bool) {
+        self.reg.a = self.compare(val, sub_carry);
+    }
+
+    // SBC HL, rr
     fn sub_16(&mut self, val: u16, sub_carry: bool) {
         let left = self.reg.hl();
-        let right = val;
-        let mut result = left.wrapping_sub(right);
-
-        if sub_carry {
-            let carry = self.reg.read_flag(0);
-            result = result.wrapping_sub(carry as u16);
-            self.detect_overflow_sub(right as u8, left as u8, carry);
-            self.detect_half_carry_sub(right as u8, left as u8, carry);
-        } else {
-            self.detect_overflow_sub(right as u8, left as u8, false);
-            self.detect_half_carry_sub(right as u8, left as u8, false);
-        }
+        let carry = sub_carry && self.reg.read_flag(0);
+        let result = left.wrapping_sub(val).wrapping_sub(carry as u16);
+        self.reg.wz = left.wrapping_add(1);
         self.reg.write_hl(result);
 
-        self.reg.set_flag(0, (left + right) as u16 > 255);
+        // the high bytes carry the same flags as an 8 bit subtraction
+        let low_borrow = (left & 0xFF) < (val & 0xFF) + carry as u16;
+        let (high_left, high_right) = ((left >> 8) as u8, (val >> 8) as u8);
+        self.detect_overflow_sub(high_left, high_right, low_borrow);
+        self.detect_half_carry_sub(high_left, high_right, low_borrow);
+
+        self.reg.set_flag(0, (left as u32) < val as u32 + carry as u32);
         self.reg.set_flag(1, true);
         self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.reg.set_flag(7, result & 0x8000 != 0);
+        self.set_undocumented((result >> 8) as u8);
     }
 
     fn and(&mut self, val: u8) {
@@ -1111,8 +1161,8 @@ impl Z80 {
         self.reg.set_flag(0, false);
         self.reg.set_flag(1, false);
         self.reg.set_flag(4, true);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
     }
 
     fn xor(&mut self, val: u8) {
@@ -1123,8 +1173,8 @@ impl Z80 {
         self.reg.set_flag(0, false);
         self.reg.set_flag(1, false);
         self.reg.set_flag(4, false);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
     }
 
     fn or(&mut self, val: u8) {
@@ -1135,165 +1185,211 @@ impl Z80 {
         self.reg.set_flag(0, false);
         self.reg.set_flag(1, false);
         self.reg.set_flag(4, false);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
     }
 
+    // unlike SUB, bits 3 and 5 come from the operand
     fn cp(&mut self, val: u8) {
+        self.compare(val, false);
+        self.set_undocumented(val);
+    }
+
+    // sets the flags for A - val and returns the result without storing it
+    fn compare(&mut self, val: u8, sub_carry: bool) -> u8 {
         let left = self.reg.a;
-        let right = val;
-        let mut result = left.wrapping_sub(right);
+        let carry = sub_carry && self.reg.read_flag(0);
+        let result = left.wrapping_sub(val).wrapping_sub(carry as u8);
 
-        self.detect_overflow_sub(right, left, false);
-        self.detect_half_carry_sub(right, left, false);
+        self.detect_overflow_sub(left, val, carry);
+        self.detect_half_carry_sub(left, val, carry);
 
-        self.reg.set_flag(0, (left + right) as u16 > 255);
+        self.reg.set_flag(0, (left as u16) < val as u16 + carry as u16);
         self.reg.set_flag(1, true);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
+        result
+    }
+
+    // DAA, adjusts A to BCD after an addition or subtraction
+    fn daa(&mut self) {
+        let a = self.reg.a;
+        let subtract = self.reg.read_flag(1);
+        let half_carry = self.reg.read_flag(4);
+        let mut carry = self.reg.read_flag(0);
+        let mut correction = 0;
+
+        if half_carry || a & 0x0F > 9 {
+            correction |= 0x06;
+        }
+        if carry || a > 0x99 {
+            correction |= 0x60;
+            carry = true;
+        }
+
+        let result = if subtract {
+            self.reg.set_flag(4, half_carry && a & 0x0F < 6);
+            a.wrapping_sub(correction)
+        } else {
+            self.reg.set_flag(4, a & 0x0F > 9);
+            a.wrapping_add(correction)
+        };
+        self.reg.a = result;
+
+        self.reg.set_flag(0, carry);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
+        self.detect_parity(result);
     }
 
     fn rot(&mut self, operator: u8, val: u8) -> u8 {
-        let mut val = val;
-        match operator {
+        let result = match operator {
             // RLC
-            0 => {
-                self.shift(val, true, false);
-            }
+            0 => self.shift(val, true, false),
             // RRC
-            1 => {
-                self.shift(val, true, true);
-            }
+            1 => self.shift(val, true, true),
             // RL
-            2 => {
-                self.shift(val, false, false);
-            }
+            2 => self.shift(val, false, false),
             // RR
-            3 => {
-                self.shift(val, false, true);
-            }
+            3 => self.shift(val, false, true),
             // SLA
             4 => {
-                self.reg.set_flag(0, (val & 1) == 0);
-                val <<= 1;
+                self.reg.set_flag(0, (val & 1 << 7) != 0);
+                val << 1
             }
             // SRA
             5 => {
-                self.reg.set_flag(0, (val & 1) == 0);
+                self.reg.set_flag(0, (val & 1) != 0);
                 // preserve 7th bit
-                let bit_7 = val & (1 << 7);
-                val = (val >> 1) | bit_7;
+                (val >> 1) | (val & 1 << 7)
             }
             // SLL
             6 => {
-                self.reg.set_flag(0, (val & 1) == 0);
-                val <<= 1;
+                self.reg.set_flag(0, (val & 1 << 7) != 0);
+                (val << 1) | 1
             }
             // SRL
-            7 => {
-                self.reg.set_flag(0, (val & 1) == 0);
-                val >>= 1;
+            _ => {
+                self.reg.set_flag(0, (val & 1) != 0);
+                val >> 1
             }
-            _ => {}
-        }
+        };
 
         self.reg.set_flag(1, false);
         self.reg.set_flag(4, false);
-        self.reg.set_flag(6, val == 0);
-        self.reg.set_flag(7, val > 127);
-        self.detect_parity(val);
-        val
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
+        self.detect_parity(result);
+        result
+    }
+
+    // RLCA, RRCA, RLA and RRA, which are the same as the CB rotations
+    // except that S, Z and P/V are left alone
+    fn rot_a(&mut self, operator: u8) {
+        let kept = self.reg.f & (1 << 7 | 1 << 6 | 1 << 2);
+        let val = self.reg.a;
+        self.reg.a = self.rot(operator, val);
+        self.reg.f = (self.reg.f & !(1 << 7 | 1 << 6 | 1 << 2)) | kept;
+        self.inc_pc();
     }
 
     fn inc_pc(&mut self) {
-        self.reg.pc += 1;
+        self.reg.pc = self.reg.pc.wrapping_add(1);
+    }
+
+    // increments the lower 7 bits of R, which happens on every opcode fetch
+    fn refresh(&mut self) {
+        self.reg.r = (self.reg.r & 0x80) | (self.reg.r.wrapping_add(1) & 0x7F);
+    }
+
+    // true if <opcode> has a (HL) operand, which becomes (IX+d) or (IY+d)
+    // after a DD or FD prefix
+    fn indexed(opcode: u8) -> bool {
+        let x: u8 = opcode >> 6;
+        let y: u8 = (opcode & 0b00111000) >> 3;
+        let z: u8 = opcode & 0b00000111;
+
+        match (x, y, z) {
+            // INC (HL), DEC (HL), LD (HL), n
+            (0, 6, 4...6) => true,
+            // HALT
+            (1, 6, 6) => false,
+            // LD r[y], r[z]
+            (1, 6, _) | (1, _, 6) => true,
+            // alu[y] r[z]
+            (2, _, 6) => true,
+            _ => false,
+        }
+    }
+
+    // true if <opcode> is a DD, ED or FD prefix
+    fn is_prefix(opcode: u8) -> bool {
+        opcode == 0xDD || opcode == 0xED || opcode == 0xFD
     }
 
     // adds d to pc
     fn jr(&mut self, d: i8) {
-        let result = self.reg.pc as i16 + d as i16;
-        if result < 0 {
-            self.reg.pc = 0;
-        } else {
-            self.reg.pc = result as u16;
-        }
-        self.reg.pc += 2;
+        self.reg.pc = self.reg.pc.wrapping_add(d as u16).wrapping_add(2);
+        self.reg.wz = self.reg.pc;
     }
 
-    // used for simplifying RLCA, RLA, RRCA, RRA instructions
+    // used for simplifying the RLC, RRC, RL and RR instructions
     //
-    // if carry_bit is true, the 7th bit is carried over to the 0th,
-    // otherwise the 0th is set to the carry flag before the instruction
+    // if carry_bit is true, the bit shifted out is carried over to the
+    // other end, otherwise the carry flag before the instruction is
     //
-    // if right is true, a right shift is performed. left otherwise
-    // TODO jesus christ what were you thinking, me?
-    fn shift(&mut self, mut value: u8, carry_bit: bool, right: bool) -> u8 {
-        if right {
-            value >>= 1;
+    // if right is true, a right shift is performed. left otherwise.
+    // either way, the bit shifted out ends up in the carry flag
+    fn shift(&mut self, value: u8, carry_bit: bool, right: bool) -> u8 {
+        let (shifted, carry, mask_shift) = if right {
+            (value >> 1, value & 1 != 0, 7)
         } else {
-            value <<= 1;
-        }
-        let carry_mask = if right { 0b00000001 } else { 0b10000000 };
-        let carry = (value & carry_mask) != 0;
-
-        let mut mask_shift = 0;
-        // if it is a right shift, the 0th bit should be carried to
-        // the 7th bit, not the other way around
-        if right {
-            mask_shift = 7;
-        }
+            (value << 1, value & 1 << 7 != 0, 0)
+        };
 
         let bit = if carry_bit { carry } else { self.reg.cc(3) };
-        if bit {
-            value |= 1 << mask_shift;
-        } else {
-            value &= !(1 << mask_shift);
-        }
-
         self.reg.set_flag(0, carry);
-        value
+        shifted | (bit as u8) << mask_shift
     }
 
-    // shift but a zero is copied to bit
-
     // decrements register at p and increments pc
     fn dec_16(&mut self, p: u8) {
-        let result = self.reg.read_16bit_r(p, true) - 1;
+        let result = self.reg.read_16bit_r(p, true).wrapping_sub(1);
         self.reg.write_16bit_r(p, true, result);
         self.inc_pc();
     }
 
     // increments register at p and increments pc
     fn inc_16(&mut self, p: u8) {
-        let result = self.reg.read_16bit_r(p, true) + 1;
+        let result = self.reg.read_16bit_r(p, true).wrapping_add(1);
         self.reg.write_16bit_r(p, true, result);
         self.inc_pc();
     }
 
-    // decrements register at y and increments pc
+    // decrements register at y and increments pc. C is left alone
     fn dec_8(&mut self, y: u8, mem: &mut Memory) {
-        let val = self.reg.read_8bit_r(y);
+        let val = self.r(y, mem);
         let result = val.wrapping_sub(1);
 
         self.reg.set_flag(1, true);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
-        self.detect_half_carry_add(val, 1, false);
-        self.detect_overflow_add(val, 1, false);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
+        self.detect_half_carry_sub(val, 1, false);
+        self.detect_overflow_sub(val, 1, false);
         self.write_r(y, result, mem);
         self.inc_pc();
     }
 
-    // icrements register at y and increments pc
+    // icrements register at y and increments pc. C is left alone
     fn inc_8(&mut self, y: u8, mem: &mut Memory) {
-        let val = self.reg.read_8bit_r(y);
+        let val = self.r(y, mem);
         let result = val.wrapping_add(1);
 
         self.reg.set_flag(1, false);
-        self.reg.set_flag(6, result == 0);
-        self.reg.set_flag(7, result > 127);
-        self.detect_half_carry_sub(val, 1, false);
-        self.detect_overflow_sub(val, 1, false);
+        self.set_sign_zero(result);
+        self.set_undocumented(result);
+        self.detect_half_carry_add(val, 1, false);
+        self.detect_overflow_add(val, 1, false);
 
         self.write_r(y, result, mem);
         self.inc_pc();
@@ -1301,32 +1397,30 @@ impl Z80 {
 
     // pushes pc + 3 to stack and then jumps to address <addr>
     fn call(&mut self, mem: &mut Memory, addr: u16) {
-        let ret_addr = self.reg.pc + 3;
+        let ret_addr = self.reg.pc.wrapping_add(3);
         self.push_stack_16(mem, ret_addr);
         self.reg.pc = addr;
+        self.reg.wz = addr;
     }
 
     // pops top stack entry into pc
     fn ret(&mut self, mem: &mut Memory) {
         self.reg.pc = self.pop_stack_16(mem);
+        self.reg.wz = self.reg.pc;
     }
 
     // returns byte at memory address pointed to by stack pointer and then
     // increments stack pointer
     fn pop_stack(&mut self, mem: &mut Memory) -> u8 {
         let byte = mem.read_byte(self.reg.sp);
-        self.reg.sp += 1;
+        self.reg.sp = self.reg.sp.wrapping_add(1);
         byte
     }
 
-    // saves byte at memory address pointed to by stack pointer and then
-    // decrements stack pointer
+    // decrements stack pointer and then saves byte at the memory address
+    // it points to. like on the real thing, the stack wraps around
     fn push_stack(&mut self, mem: &mut Memory, byte: u8) {
-        if self.reg.sp > 0 {
-            self.reg.sp -= 1;
-        } else {
-            println!("Stack overflow!");
-        }
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
         mem.write_byte(byte, self.reg.sp);
     }
 
@@ -1344,16 +1438,15 @@ impl Z80 {
     // detects if a half carry occurs in an operation left + right
     // and sets flag accordingly
     fn detect_half_carry_add(&mut self, left: u8, right: u8, carry: bool) {
-        let mut left = left + (carry as u8);
-        self.reg.set_flag(4, (left & 0x0F) + (right & 0x0F) > 0x0F);
+        self.reg
+            .set_flag(4, (left & 0x0F) + (right & 0x0F) + (carry as u8) > 0x0F);
     }
 
     // detects if a half carry occurs in operation left - right
     // and sets flag accordingly
     fn detect_half_carry_sub(&mut self, left: u8, right: u8, carry: bool) {
-        let mut left = left - (carry as u8);
         self.reg
-            .set_flag(4, (left & 0x0F).wrapping_sub(right & 0x0F) > 0x0F);
+            .set_flag(4, (left & 0x0F) < (right & 0x0F) + (carry as u8));
     }
 
     // detects if an overflow occurs in operation left + right
@@ -1377,14 +1470,20 @@ impl Z80 {
     }
 
     // sets parity flag accordingly to the given number
-    fn detect_parity(&mut self, mut num: u8) {
-        let mut number_of_ones = 0;
-        while num != 0 {
-            if num & 1 != 0 {
-                number_of_ones += 1
-            };
-            num >>= 1;
-        }
+    fn detect_parity(&mut self, num: u8) {
+        self.reg.set_flag(2, num.count_ones() % 2 == 0);
+    }
+
+    // sets S and Z according to <result>
+    fn set_sign_zero(&mut self, result: u8) {
+        self.reg.set_flag(6, result == 0);
+        self.reg.set_flag(7, result > 127);
+    }
+
+    // copies bits 3 and 5 of <val> into the undocumented bits 3 and 5 of F
+    fn set_undocumented(&mut self, val: u8) {
+        self.reg.set_flag(3, val & 1 << 3 != 0);
+        self.reg.set_flag(5, val & 1 << 5 != 0);
     }
 }
 
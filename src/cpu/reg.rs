@@ -1,3 +1,4 @@
+use savestate::{StateReader, StateWriter};
 use std::fmt;
 
 pub struct Registers {
@@ -22,6 +23,10 @@ pub struct Registers {
     pub r: u8,
 
     pub pc: u16,
+
+    // internal register also known as MEMPTR. it can only be observed
+    // through bits 3 and 5 of F after BIT n, (HL)
+    pub wz: u16,
 }
 
 impl Registers {
@@ -48,6 +53,8 @@ impl Registers {
             r: 0,
 
             pc: 0,
+
+            wz: 0,
         }
     }
 
@@ -239,6 +246,42 @@ impl Registers {
     pub fn patch_iy(&mut self, patch: bool) {
         self.iy_patched = patch;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[
+            self.b, self.c, self.d, self.e, self.h, self.l, self.a, self.f, self.ixh, self.ixl,
+            self.iyh, self.iyl, self.i, self.r,
+        ]);
+        state.bool(self.ix_patched);
+        state.bool(self.iy_patched);
+        state.u16(self.sp);
+        state.u16(self.pc);
+        state.u16(self.wz);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let bytes = state.bytes(14)?;
+        self.b = bytes[0];
+        self.c = bytes[1];
+        self.d = bytes[2];
+        self.e = bytes[3];
+        self.h = bytes[4];
+        self.l = bytes[5];
+        self.a = bytes[6];
+        self.f = bytes[7];
+        self.ixh = bytes[8];
+        self.ixl = bytes[9];
+        self.iyh = bytes[10];
+        self.iyl = bytes[11];
+        self.i = bytes[12];
+        self.r = bytes[13];
+        self.ix_patched = state.bool()?;
+        self.iy_patched = state.bool()?;
+        self.sp = state.u16()?;
+        self.pc = state.u16()?;
+        self.wz = state.u16()?;
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Registers {
@@ -250,7 +293,7 @@ impl fmt::Debug for Registers {
              d: 0x{:x}, e: 0x{:x}, h: 0x{:x}, l: 0x{:x}\n\n\
              af: 0x{:x}, bc: 0x{:x}, de: 0x{:x}, hl: 0x{:x}, sp: {:x}\n\
              ixh: 0x{:x}, ixl: 0x{:x}, iyh: 0x{:x}, iyl: 0x{:x}\n\
-             i: 0x{:x}, r: {:x}",
+             i: 0x{:x}, r: {:x}, wz: 0x{:x}",
             self.pc,
             self.a,
             self.f,
@@ -270,7 +313,8 @@ impl fmt::Debug for Registers {
             self.iyh,
             self.iyl,
             self.i,
-            self.r
+            self.r,
+            self.wz
         )
     }
 }
@@ -1,6 +1,9 @@
 use super::*;
+use cpu::cpm::Cpm;
 use cpu::io::TestIO;
 use cpu::mem::TestMemory;
+use std::env;
+use std::path::Path;
 
 #[test]
 fn test_run_opcodes() {
@@ -74,9 +77,11 @@ fn test_8bit_inc_dec() {
     assert_eq!(cpu.reg.read_flag(1), true);
 
     // test overflow
-    cpu.reg.b = 127;
+    cpu.reg.b = 0x80;
     cpu.run_opcode(0x05, &mut mem, &mut io, false);
     assert_eq!(cpu.reg.read_flag(2), true);
+    cpu.run_opcode(0x04, &mut mem, &mut io, false);
+    assert_eq!(cpu.reg.read_flag(2), true);
 }
 
 #[test]
@@ -88,14 +93,18 @@ fn test_shift() {
     assert_eq!(result, 0b0000_1010);
     assert_eq!(cpu.reg.cc(3), false);
 
-    result = cpu.shift(0b0101_0101, false, false);
+    result = cpu.shift(0b1101_0101, false, false);
     assert_eq!(result, 0b1010_1010);
     assert_eq!(cpu.reg.cc(3), true);
 
     cpu.reg.set_flag(0, false);
-    result = cpu.shift(0b0000_0110, true, true);
+    result = cpu.shift(0b0000_0111, true, true);
     assert_eq!(result, 0b1000_0011);
     assert_eq!(cpu.reg.cc(3), true);
+
+    result = cpu.shift(0b0000_0110, false, true);
+    assert_eq!(result, 0b1000_0011);
+    assert_eq!(cpu.reg.cc(3), false);
 }
 
 #[test]
@@ -120,7 +129,9 @@ fn test_halt() {
     let mut io = TestIO::new();
 
     cpu.run_opcode(0x76, &mut memory, &mut io, false);
-    assert_eq!(cpu.run_opcodes(100, &mut memory, &mut io), 0);
+    // a halted cpu keeps running NOPs
+    assert_eq!(cpu.run_opcodes(100, &mut memory, &mut io), 400);
+    assert_eq!(cpu.reg.pc, 1);
 }
 
 #[test]
@@ -213,7 +224,11 @@ fn test_bit_test() {
     cpu.reg.b = 2;
     memory.ram[0] = 0xCB;
     memory.ram[1] = 0x48;
+    memory.ram[2] = 0xCB;
+    memory.ram[3] = 0x40;
     cpu.run_opcode(memory.read_byte(0), &mut memory, &mut io, false);
+    assert_eq!(cpu.reg.read_flag(6), false);
+    cpu.run_opcode(memory.read_byte(2), &mut memory, &mut io, false);
     assert_eq!(cpu.reg.read_flag(6), true);
 }
 
@@ -252,14 +267,95 @@ fn test_interrupt() {
     let mut memory = TestMemory::new();
     let mut io = TestIO::new();
 
+    // IM 2, EI, NOP
+    memory.ram[0] = 0xED;
+    memory.ram[1] = 0x5E;
+    memory.ram[2] = 0xFB;
     memory.ram[770] = 0x09;
     cpu.interrupt(2);
     cpu.reg.i = 3;
-    cpu.reg.sp = 100;;
-    cpu.run_opcode(0x00, &mut memory, &mut io, false);
+    cpu.reg.sp = 100;
+    cpu.run_opcodes(2, &mut memory, &mut io);
+    assert_eq!(cpu.reg.pc, 3);
+
+    // EI delays acceptance until after the NOP
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 4);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 19);
+    assert_eq!(cpu.reg.pc, 0x09);
+    assert_eq!(cpu.pop_stack_16(&mut memory), 4);
+    assert_eq!(cpu.iff1, false);
+}
+
+#[test]
+fn test_clear_interrupt() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    cpu.iff1 = true;
+    cpu.interrupt(0xFF);
+    cpu.clear_interrupt();
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 4);
+    assert_eq!(cpu.reg.pc, 1);
+    assert_eq!(cpu.iff1, true);
+}
+
+#[test]
+fn test_interrupt_modes() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    cpu.reg.sp = 100;
+    cpu.reg.pc = 0x10;
+
+    // interrupts start disabled
+    cpu.interrupt(0xCF);
+    cpu.run_opcodes(1, &mut memory, &mut io);
+    assert_eq!(cpu.reg.pc, 0x11);
+
+    // mode 0 runs the RST 08h on the data bus
+    cpu.iff1 = true;
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 13);
+    assert_eq!(cpu.reg.pc, 0x08);
+    assert_eq!(cpu.pop_stack_16(&mut memory), 0x11);
+
+    // mode 1 always goes to 0x38 and wakes the cpu from HALT
+    memory.ram[0x08] = 0x76;
+    cpu.run_opcodes(1, &mut memory, &mut io);
+    assert!(cpu.halted);
+    cpu.interrupt_mode = 1;
+    cpu.iff1 = true;
+    cpu.interrupt(0xFF);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 13);
+    assert!(!cpu.halted);
+    assert_eq!(cpu.reg.pc, 0x38);
+    assert_eq!(cpu.pop_stack_16(&mut memory), 0x09);
+}
+
+#[test]
+fn test_nmi() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
 
-    // taking into account the PC increment
-    assert_eq!(cpu.reg.pc, 0x0A);
+    // RETN
+    memory.ram[0x66] = 0xED;
+    memory.ram[0x67] = 0x45;
+    cpu.reg.sp = 100;
+    cpu.reg.pc = 0x20;
+    cpu.iff1 = true;
+    cpu.iff2 = true;
+
+    cpu.nmi();
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 11);
+    assert_eq!(cpu.reg.pc, 0x66);
+    assert_eq!(cpu.iff1, false);
+    assert_eq!(cpu.iff2, true);
+
+    cpu.run_opcodes(1, &mut memory, &mut io);
+    assert_eq!(cpu.reg.pc, 0x20);
+    assert_eq!(cpu.iff1, true);
 }
 
 #[test]
@@ -334,3 +430,258 @@ fn test_ix_iy_bit_operations() {
     cpu.run_opcode(memory.read_byte(0), &mut memory, &mut io, false);
     assert_eq!(memory.ram[0x0202], 0b0001_0000);
 }
+
+#[test]
+fn test_conditional_timing() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    // JP Z, 0x0100 isn't taken but still costs 10
+    memory.ram[0] = 0xCA;
+    memory.write_word(0x0100, 1);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 10);
+    assert_eq!(cpu.reg.pc, 3);
+
+    // CALL NZ, 0x0100 is taken
+    memory.ram[3] = 0xC4;
+    memory.write_word(0x0100, 4);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 17);
+    assert_eq!(cpu.reg.pc, 0x0100);
+
+    // JR Z, 5 isn't taken, RET NZ is
+    memory.ram[0x0100] = 0x28;
+    memory.ram[0x0101] = 0x05;
+    memory.ram[0x0102] = 0xC0;
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 7);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 11);
+    assert_eq!(cpu.reg.pc, 6);
+}
+
+#[test]
+fn test_prefixed_timing() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    // RLC (HL)
+    memory.ram[0] = 0xCB;
+    memory.ram[1] = 0x06;
+    // INC (IX-2)
+    memory.ram[2] = 0xDD;
+    memory.ram[3] = 0x34;
+    memory.ram[4] = 0xFE;
+    // BIT 0, (IY+1)
+    memory.ram[5] = 0xFD;
+    memory.ram[6] = 0xCB;
+    memory.ram[7] = 0x01;
+    memory.ram[8] = 0x46;
+    // OUT (0x10), A
+    memory.ram[9] = 0xD3;
+    memory.ram[10] = 0x10;
+
+    cpu.reg.patch_ix(true);
+    cpu.reg.write_hl(0x0202);
+    cpu.reg.patch_ix(false);
+
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 15);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 23);
+    assert_eq!(memory.ram[0x0200], 1);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 20);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 11);
+    assert_eq!(cpu.reg.pc, 11);
+}
+
+#[test]
+fn test_block_repeat_timing() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    // LDIR copying 2 bytes from 0x0100 to 0x0200
+    memory.ram[0] = 0xED;
+    memory.ram[1] = 0xB0;
+    memory.ram[0x0100] = 0x12;
+    memory.ram[0x0101] = 0x34;
+    cpu.reg.write_hl(0x0100);
+    cpu.reg.write_de(0x0200);
+    cpu.reg.write_bc(2);
+
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 21);
+    assert_eq!(cpu.reg.pc, 0);
+    assert_eq!(cpu.run_opcodes(1, &mut memory, &mut io), 16);
+    assert_eq!(cpu.reg.pc, 2);
+    assert_eq!(memory.ram[0x0201], 0x34);
+}
+
+#[test]
+fn test_refresh() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    cpu.reg.r = 0xFF;
+    // NOP, then LD IX, 0
+    memory.ram[1] = 0xDD;
+    memory.ram[2] = 0x21;
+    cpu.run_opcodes(2, &mut memory, &mut io);
+    // bit 7 is never changed
+    assert_eq!(cpu.reg.r, 0x82);
+}
+
+#[test]
+fn test_undocumented_flags() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    // ADD A, B takes bits 3 and 5 from the result
+    cpu.reg.a = 0x20;
+    cpu.reg.b = 0x08;
+    cpu.run_opcode(0x80, &mut memory, &mut io, false);
+    assert_eq!(cpu.reg.f & 0b0010_1000, 0b0010_1000);
+
+    // CP B takes them from the operand
+    cpu.reg.a = 0x28;
+    cpu.reg.b = 0x01;
+    cpu.run_opcode(0xB8, &mut memory, &mut io, false);
+    assert_eq!(cpu.reg.f & 0b0010_1000, 0);
+
+    // BIT 0, (HL) takes them from MEMPTR, set here by LD A, (nn)
+    memory.ram[0] = 0x3A;
+    memory.write_word(0x27FF, 1);
+    memory.ram[3] = 0xCB;
+    memory.ram[4] = 0x46;
+    cpu.reg.pc = 0;
+    cpu.run_opcodes(2, &mut memory, &mut io);
+    assert_eq!(cpu.reg.wz, 0x2800);
+    assert_eq!(cpu.reg.f & 0b0010_1000, 0b0010_1000);
+}
+
+#[test]
+fn test_flags() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    // SUB sets carry on borrow
+    cpu.reg.a = 0x10;
+    cpu.reg.b = 0x20;
+    cpu.run_opcode(0x90, &mut memory, &mut io, false);
+    assert_eq!(cpu.reg.a, 0xF0);
+    assert_eq!(cpu.reg.read_flag(0), true);
+    assert_eq!(cpu.reg.read_flag(7), true);
+
+    // DAA after 0x15 + 0x27
+    cpu.reg.a = 0x15;
+    cpu.reg.b = 0x27;
+    cpu.run_opcode(0x80, &mut memory, &mut io, false);
+    cpu.run_opcode(0x27, &mut memory, &mut io, false);
+    assert_eq!(cpu.reg.a, 0x42);
+    assert_eq!(cpu.reg.read_flag(0), false);
+
+    // parity
+    cpu.reg.a = 0b0000_0011;
+    cpu.reg.b = 0xFF;
+    cpu.run_opcode(0xA0, &mut memory, &mut io, false);
+    assert_eq!(cpu.reg.read_flag(2), true);
+
+    // ADC HL, DE overflowing into the sign bit
+    cpu.reg.write_hl(0x7FFF);
+    cpu.reg.write_de(0x0001);
+    cpu.reg.set_flag(0, false);
+    memory.ram[cpu.reg.pc as usize] = 0xED;
+    memory.ram[cpu.reg.pc as usize + 1] = 0x5A;
+    cpu.run_opcodes(1, &mut memory, &mut io);
+    assert_eq!(cpu.reg.hl(), 0x8000);
+    assert_eq!(cpu.reg.read_flag(2), true);
+    assert_eq!(cpu.reg.read_flag(4), true);
+    assert_eq!(cpu.reg.read_flag(7), true);
+}
+
+#[test]
+fn test_rld_rrd() {
+    let mut cpu = Z80::new();
+    let mut memory = TestMemory::new();
+    let mut io = TestIO::new();
+
+    // RLD, RRD
+    memory.ram[0] = 0xED;
+    memory.ram[1] = 0x6F;
+    memory.ram[2] = 0xED;
+    memory.ram[3] = 0x67;
+    memory.ram[0x0100] = 0x34;
+    cpu.reg.write_hl(0x0100);
+    cpu.reg.a = 0x12;
+
+    cpu.run_opcodes(1, &mut memory, &mut io);
+    assert_eq!(cpu.reg.a, 0x13);
+    assert_eq!(memory.ram[0x0100], 0x42);
+    cpu.run_opcodes(1, &mut memory, &mut io);
+    assert_eq!(cpu.reg.a, 0x12);
+    assert_eq!(memory.ram[0x0100], 0x34);
+}
+
+#[test]
+fn test_cpm_bdos() {
+    let mut cpm = Cpm::new();
+    cpm.load(&[
+        // LD C, 2; LD E, 'A'; CALL 5
+        0x0E, 0x02, 0x1E, 0x41, 0xCD, 0x05, 0x00,
+        // LD C, 9; LD DE, 0x0113; CALL 5
+        0x0E, 0x09, 0x11, 0x13, 0x01, 0xCD, 0x05, 0x00,
+        // LD HL, (6); RET
+        0x2A, 0x06, 0x00, 0xC9,
+        // "ok$"
+        0x6F, 0x6B, 0x24,
+    ]);
+    cpm.run().unwrap();
+
+    assert_eq!(cpm.output(), "Aok");
+    assert!(cpm.cycles() > 0);
+}
+
+#[test]
+fn test_cpm_unsupported_bdos() {
+    let mut cpm = Cpm::new();
+    // LD C, 1; CALL 5, console input
+    cpm.load(&[0x0E, 0x01, 0xCD, 0x05, 0x00]);
+
+    assert_eq!(cpm.run(), Err("Unsupported BDOS function 1".to_string()));
+}
+
+// runs the CP/M program <name> from the directory in Z80_TEST_DIR and
+// fails listing every instruction group that didn't pass. these take a
+// long time, so run them in release mode:
+//
+// Z80_TEST_DIR=path/to/roms cargo test --release -- --ignored
+fn run_cpm_test(name: &str) {
+    let dir = env::var("Z80_TEST_DIR").expect("Z80_TEST_DIR is not set");
+    let mut cpm = Cpm::new();
+    cpm.load_file(&Path::new(&dir).join(name))
+        .expect("Could not read test program");
+    let result = cpm.run();
+    print!("{}", cpm.output());
+    result.unwrap();
+
+    let groups = cpm.groups();
+    let failed: Vec<&str> = groups
+        .iter()
+        .filter(|group| !group.passed)
+        .map(|group| group.name.as_str())
+        .collect();
+    assert!(!groups.is_empty(), "{} reported no results", name);
+    assert!(failed.is_empty(), "{} failed: {:?}", name, failed);
+}
+
+#[test]
+#[ignore]
+fn test_zexdoc() {
+    run_cpm_test("zexdoc.com");
+}
+
+#[test]
+#[ignore]
+fn test_zexall() {
+    run_cpm_test("zexall.com");
+}
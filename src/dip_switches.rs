@@ -0,0 +1,201 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coinage {
+    FreePlay,
+    OneCoinOneCredit,
+    OneCoinTwoCredits,
+    TwoCoinsOneCredit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lives {
+    One,
+    Two,
+    Three,
+    Five,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BonusLife {
+    At10000,
+    At15000,
+    At20000,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difficulty {
+    Normal,
+    Hard,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GhostNames {
+    Normal,
+    Alternate,
+}
+
+/// Names of the settings accepted by DipSwitches::set, which are also the
+/// command line options (prefixed with --) and config file keys
+pub const SETTINGS: [&str; 5] = ["coinage", "lives", "bonus-life", "difficulty", "ghost-names"];
+
+/// The DIP switch bank read at 0x5080
+///
+/// bits 0-1: coinage, 2-3: lives, 4-5: bonus life, 6: difficulty
+/// (low is hard), 7: ghost names (low is alternate)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DipSwitches {
+    pub coinage: Coinage,
+    pub lives: Lives,
+    pub bonus_life: BonusLife,
+    pub difficulty: Difficulty,
+    pub ghost_names: GhostNames,
+}
+
+impl DipSwitches {
+    /// The factory settings
+    pub fn new() -> Self {
+        DipSwitches {
+            coinage: Coinage::OneCoinOneCredit,
+            lives: Lives::Three,
+            bonus_life: BonusLife::At10000,
+            difficulty: Difficulty::Normal,
+            ghost_names: GhostNames::Normal,
+        }
+    }
+
+    /// The byte the switches put on the data bus
+    pub fn value(&self) -> u8 {
+        let coinage = match self.coinage {
+            Coinage::FreePlay => 0,
+            Coinage::OneCoinOneCredit => 1,
+            Coinage::OneCoinTwoCredits => 2,
+            Coinage::TwoCoinsOneCredit => 3,
+        };
+        let lives = match self.lives {
+            Lives::One => 0,
+            Lives::Two => 1,
+            Lives::Three => 2,
+            Lives::Five => 3,
+        };
+        let bonus_life = match self.bonus_life {
+            BonusLife::At10000 => 0,
+            BonusLife::At15000 => 1,
+            BonusLife::At20000 => 2,
+            BonusLife::Never => 3,
+        };
+        let difficulty = match self.difficulty {
+            Difficulty::Normal => 1,
+            Difficulty::Hard => 0,
+        };
+        let ghost_names = match self.ghost_names {
+            GhostNames::Normal => 1,
+            GhostNames::Alternate => 0,
+        };
+
+        coinage | lives << 2 | bonus_life << 4 | difficulty << 6 | ghost_names << 7
+    }
+
+    /// Changes setting <name> (one of SETTINGS) to <value>, e.g.
+    /// set("lives", "5") or set("coinage", "2c1c")
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match (name, value) {
+            ("coinage", "free") => self.coinage = Coinage::FreePlay,
+            ("coinage", "1c1c") => self.coinage = Coinage::OneCoinOneCredit,
+            ("coinage", "1c2c") => self.coinage = Coinage::OneCoinTwoCredits,
+            ("coinage", "2c1c") => self.coinage = Coinage::TwoCoinsOneCredit,
+
+            ("lives", "1") => self.lives = Lives::One,
+            ("lives", "2") => self.lives = Lives::Two,
+            ("lives", "3") => self.lives = Lives::Three,
+            ("lives", "5") => self.lives = Lives::Five,
+
+            ("bonus-life", "10000") => self.bonus_life = BonusLife::At10000,
+            ("bonus-life", "15000") => self.bonus_life = BonusLife::At15000,
+            ("bonus-life", "20000") => self.bonus_life = BonusLife::At20000,
+            ("bonus-life", "none") => self.bonus_life = BonusLife::Never,
+
+            ("difficulty", "normal") => self.difficulty = Difficulty::Normal,
+            ("difficulty", "hard") => self.difficulty = Difficulty::Hard,
+
+            ("ghost-names", "normal") => self.ghost_names = GhostNames::Normal,
+            ("ghost-names", "alternate") => self.ghost_names = GhostNames::Alternate,
+
+            (name, value) => {
+                return if SETTINGS.contains(&name) {
+                    Err(format!("Invalid value for {}: {}", name, value))
+                } else {
+                    Err(format!("Unknown DIP switch setting: {}", name))
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the settings in the config file at <path>
+    pub fn load_config(&mut self, path: &Path) -> Result<(), String> {
+        let mut config = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut config))
+            .map_err(|why| format!("Could not read {}: {}", path.display(), why))?;
+        self.parse_config(&config)
+    }
+
+    /// Applies settings written one per line as "name = value". Blank lines
+    /// and lines starting with # are skipped
+    pub fn parse_config(&mut self, config: &str) -> Result<(), String> {
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut split = line.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(name), Some(value)) => self.set(name.trim(), value.trim())?,
+                _ => return Err(format!("Line {}: expected name = value", i + 1)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_value() {
+        assert_eq!(DipSwitches::new().value(), 0b1100_1001);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut dips = DipSwitches::new();
+
+        dips.set("coinage", "free").unwrap();
+        dips.set("lives", "5").unwrap();
+        dips.set("bonus-life", "none").unwrap();
+        dips.set("difficulty", "hard").unwrap();
+        dips.set("ghost-names", "alternate").unwrap();
+        assert_eq!(dips.value(), 0b0011_1100);
+
+        assert!(dips.set("lives", "4").is_err());
+        assert!(dips.set("speed", "fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let mut dips = DipSwitches::new();
+
+        dips.parse_config("# cheap game\ncoinage = 1c2c\n\nlives=2\n")
+            .unwrap();
+        assert_eq!(dips.coinage, Coinage::OneCoinTwoCredits);
+        assert_eq!(dips.lives, Lives::Two);
+
+        assert!(dips.parse_config("lives").is_err());
+    }
+}
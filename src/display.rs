@@ -1,80 +1,253 @@
+use graphics_cache::{Graphic, GraphicsCache};
 use rom::Roms;
 
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 288;
+// the frame buffer is packed RGB24, 3 bytes a pixel
+pub const PITCH: usize = WIDTH * 3;
 
-pub struct Display<'a> {
-    roms: &'a Roms,
-    frame_buffer: [[Color; 224]; 288],
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
-impl<'a> Display<'a> {
-    pub fn new(roms: &'a Roms) -> Self {
-        Display {
-            roms: roms,
-            frame_buffer: [[Color::RGB(0, 0, 0); 224]; 288],
-        }
+impl Color {
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r: r, g: g, b: b }
     }
+}
 
-    pub fn draw_tile(&mut self, mut x: usize, mut y: usize, tile: usize, palette: usize) {
-        x *= 8;
-        y *= 8;
-        let slice_index = tile * 64;
+/// How the picture is fitted to the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VideoSettings {
+    /// Window size as a multiple of 224x288
+    pub scale: u32,
+    /// Only scale by whole numbers when the window is resized or fullscreen,
+    /// so every emulated pixel is the same size
+    pub integer_scaling: bool,
+    pub fullscreen: bool,
+}
 
-        self.draw_slice(x, y + 4, slice_index, palette, false, false, false);
-        self.draw_slice(x, y, slice_index + 32, palette, false, false, false);
+impl VideoSettings {
+    pub fn new() -> Self {
+        VideoSettings {
+            scale: 2,
+            integer_scaling: true,
+            fullscreen: false,
+        }
     }
+}
 
-    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: usize, palette: usize) {
-        let slice_index = sprite * 256;
-
-        self.draw_slice(x + 8, y + 12, slice_index, palette, true, false, false);
-        self.draw_slice(x + 8, y, slice_index + 32, palette, true, false, false);
-        self.draw_slice(x + 8, y + 4, slice_index + 64, palette, true, false, false);
-        self.draw_slice(x + 8, y + 8, slice_index + 96, palette, true, false, false);
-        self.draw_slice(x, y + 12, slice_index + 128, palette, true, false, false);
-        self.draw_slice(x, y, slice_index + 160, palette, true, false, false);
-        self.draw_slice(x, y + 4, slice_index + 192, palette, true, false, false);
-        self.draw_slice(x, y + 8, slice_index + 224, palette, true, false, false);
-    }
+pub struct Display<'a> {
+    cache: GraphicsCache<'a>,
+    frame_buffer: Vec<u8>,
+}
 
-    pub fn show(&self, canvas: &mut Canvas<Window>) {
-        for x in 0..224 {
-            for y in 0..288 {
-                let x_coord = (x * 2) as i32;
-                let y_coord = (y * 2) as i32;
-                canvas.set_draw_color(self.frame_buffer[y][x]);
-                let result = canvas.fill_rect(Rect::new(x_coord, y_coord, 2, 2));
-            }
+impl<'a> Display<'a> {
+    pub fn new(roms: &'a Roms) -> Self {
+        Display {
+            cache: GraphicsCache::new(roms),
+            frame_buffer: vec![0; PITCH * HEIGHT],
         }
     }
 
-    // draws an 8x4 slice of pixels. if sprite is true, it use sprite rom, tile rom otherwise
-    fn draw_slice(
+    pub fn draw_tile(&mut self, x: usize, y: usize, tile: usize, palette: usize) {
+        self.draw_tile_flipped(x, y, tile, palette, false, false);
+    }
+
+    /// Draws <tile> at tile coordinates (<x>, <y>), mirrored horizontally
+    /// and/or vertically. Flipping both rotates it 180 degrees, which is
+    /// what the flipped cocktail screen needs
+    pub fn draw_tile_flipped(
         &mut self,
         x: usize,
         y: usize,
-        mut index: usize,
+        tile: usize,
+        palette: usize,
+        x_flip: bool,
+        y_flip: bool,
+    ) {
+        let (x, y) = ((x * 8) as i32, (y * 8) as i32);
+        let graphic = self.cache.tile(tile, palette, x_flip, y_flip);
+        blit(&mut self.frame_buffer, graphic, x, y, false);
+    }
+
+    /// Draws 16x16 sprite number <sprite> with its top left corner at
+    /// (<x>, <y>). Pixels of colour 0 are transparent and anything outside
+    /// the screen is clipped
+    pub fn draw_sprite(
+        &mut self,
+        x: i32,
+        y: i32,
+        sprite: usize,
         palette: usize,
-        sprite: bool,
         x_flip: bool,
         y_flip: bool,
     ) {
-        let video_rom = &if sprite {
-            self.roms.sprite_rom
-        } else {
-            self.roms.tile_rom
-        };
-        let palette = &self.roms.palette_rom[palette];
-
-        for x_offset in (0..8).rev() {
-            for y_offset in (0..4).rev() {
-                let color_index = palette[video_rom[index] as usize];
-                self.frame_buffer[y + y_offset][x + x_offset] = self.roms.color_rom[color_index];
-                index += 1;
+        let graphic = self.cache.sprite(sprite, palette, x_flip, y_flip);
+        blit(&mut self.frame_buffer, graphic, x, y, true);
+    }
+
+    /// The frame as packed RGB24 rows of PITCH bytes
+    pub fn pixels(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let i = y * PITCH + x * 3;
+        Color::rgb(
+            self.frame_buffer[i],
+            self.frame_buffer[i + 1],
+            self.frame_buffer[i + 2],
+        )
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let i = y * PITCH + x * 3;
+        self.frame_buffer[i] = color.r;
+        self.frame_buffer[i + 1] = color.g;
+        self.frame_buffer[i + 2] = color.b;
+    }
+}
+
+// copies <graphic> into <frame_buffer> with its top left corner at (<x>, <y>),
+// clipped to the screen. rows entirely on screen are copied in one go unless
+// colour 0 has to be left out
+fn blit(frame_buffer: &mut [u8], graphic: &Graphic, x: i32, y: i32, transparent: bool) {
+    let size = graphic.size as i32;
+    let whole_rows = !transparent && x >= 0 && x + size <= WIDTH as i32;
+
+    for row in 0..size {
+        let pixel_y = y + row;
+        if pixel_y < 0 || pixel_y >= HEIGHT as i32 {
+            continue;
+        }
+        let source = (row * size) as usize * 3;
+
+        if whole_rows {
+            let start = pixel_y as usize * PITCH + x as usize * 3;
+            let len = graphic.size * 3;
+            frame_buffer[start..start + len].copy_from_slice(&graphic.pixels[source..source + len]);
+            continue;
+        }
+
+        for col in 0..size {
+            let pixel_x = x + col;
+            let pixel = (row * size + col) as usize;
+            if !on_screen(pixel_x, pixel_y) || (transparent && !graphic.opaque[pixel]) {
+                continue;
+            }
+            let start = pixel_y as usize * PITCH + pixel_x as usize * 3;
+            let color = &graphic.pixels[pixel * 3..pixel * 3 + 3];
+            frame_buffer[start..start + 3].copy_from_slice(color);
+        }
+    }
+}
+
+fn on_screen(x: i32, y: i32) -> bool {
+    x >= 0 && x < WIDTH as i32 && y >= 0 && y < HEIGHT as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roms() -> Roms {
+        let mut roms = Roms::new();
+        roms.color_rom[1] = Color::rgb(255, 0, 0);
+        roms.color_rom[2] = Color::rgb(0, 255, 0);
+        // palette 1 maps pixel value n to colour n
+        roms.palette_rom[1] = [0, 1, 2, 3];
+        roms
+    }
+
+    #[test]
+    fn test_sprite_transparency() {
+        let mut roms = roms();
+        // sprite 1 has its top half opaque and its bottom half colour 0
+        for i in 0..256 {
+            let top = match (i / 32) % 4 {
+                1 | 2 => true,
+                _ => false,
+            };
+            roms.sprite_rom[256 + i] = if top { 1 } else { 0 };
+        }
+        let mut display = Display::new(&roms);
+        for y in 8..24 {
+            for x in 8..24 {
+                display.set_pixel(x, y, Color::rgb(0, 255, 0));
             }
         }
+
+        display.draw_sprite(8, 8, 1, 1, false, false);
+        assert_eq!(display.pixel(8, 8), Color::rgb(255, 0, 0));
+        assert_eq!(display.pixel(15, 15), Color::rgb(255, 0, 0));
+        // colour 0 leaves the background alone
+        assert_eq!(display.pixel(8, 16), Color::rgb(0, 255, 0));
+    }
+
+    #[test]
+    fn test_tile_flip() {
+        let mut roms = roms();
+        // tile 0 has a single pixel lit in its top left corner, the last one
+        // stored for the top half
+        roms.tile_rom[32 + 31] = 1;
+        let red = Color::rgb(255, 0, 0);
+
+        let mut display = Display::new(&roms);
+        display.draw_tile(0, 0, 0, 1);
+        assert_eq!(display.pixel(0, 0), red);
+
+        let mut display = Display::new(&roms);
+        display.draw_tile_flipped(0, 0, 0, 1, true, false);
+        assert_eq!(display.pixel(7, 0), red);
+        assert_eq!(display.pixel(0, 0), Color::rgb(0, 0, 0));
+
+        let mut display = Display::new(&roms);
+        display.draw_tile_flipped(1, 1, 0, 1, true, true);
+        assert_eq!(display.pixel(15, 15), red);
+    }
+
+    #[test]
+    fn test_sprite_flip() {
+        let mut roms = roms();
+        // top left pixel of sprite 0, in the slice drawn at (0, 0)
+        roms.sprite_rom[160 + 31] = 1;
+        let red = Color::rgb(255, 0, 0);
+
+        let mut display = Display::new(&roms);
+        display.draw_sprite(0, 0, 0, 1, false, false);
+        assert_eq!(display.pixel(0, 0), red);
+
+        let mut display = Display::new(&roms);
+        display.draw_sprite(0, 0, 0, 1, true, false);
+        assert_eq!(display.pixel(15, 0), red);
+
+        let mut display = Display::new(&roms);
+        display.draw_sprite(0, 0, 0, 1, false, true);
+        assert_eq!(display.pixel(0, 15), red);
+
+        let mut display = Display::new(&roms);
+        display.draw_sprite(0, 0, 0, 1, true, true);
+        assert_eq!(display.pixel(15, 15), red);
+    }
+
+    #[test]
+    fn test_sprite_clipping() {
+        let mut roms = roms();
+        for pixel in roms.sprite_rom[..256].iter_mut() {
+            *pixel = 1;
+        }
+        let mut display = Display::new(&roms);
+
+        let red = Color::rgb(255, 0, 0);
+        display.draw_sprite(-8, -8, 0, 1, false, false);
+        display.draw_sprite(WIDTH as i32 - 8, HEIGHT as i32 - 8, 0, 1, false, false);
+        assert_eq!(display.pixel(0, 0), red);
+        assert_eq!(display.pixel(7, 7), red);
+        assert_eq!(display.pixel(8, 8), Color::rgb(0, 0, 0));
+        assert_eq!(display.pixel(WIDTH - 1, HEIGHT - 1), red);
     }
 }
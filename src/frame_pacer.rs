@@ -0,0 +1,122 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// a frame is 50688 cycles of the 3.072 MHz CPU, 16.5 ms or 60.606 Hz
+const FRAME_NANOS: u64 = 16_500_000;
+// slow motion runs at a quarter of the normal speed
+const SLOW_MOTION_FACTOR: u32 = 4;
+// sleeping is only accurate to a millisecond or so, so the rest of the wait
+// is spent spinning
+const SPIN_TIME: Duration = Duration::from_millis(2);
+// if we fall this far behind (the window was being dragged, the machine is
+// too slow) we give up on catching up rather than running frames back to back
+const MAX_LAG_FRAMES: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    Normal,
+    // no waiting at all, as fast as the host can go
+    Turbo,
+    SlowMotion,
+}
+
+/// Keeps the main loop to one frame per 16.5 ms, the arcade's 60.606 Hz
+pub struct FramePacer {
+    speed: Speed,
+    // when the next frame is due
+    deadline: Instant,
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        FramePacer {
+            speed: Speed::Normal,
+            deadline: Instant::now(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Switches to <speed>, or back to normal speed if it's already the
+    /// current one
+    pub fn toggle(&mut self, speed: Speed) {
+        self.speed = if self.speed == speed {
+            Speed::Normal
+        } else {
+            speed
+        };
+        self.deadline = Instant::now();
+    }
+
+    /// How long a frame lasts at the current speed
+    pub fn frame_duration(&self) -> Option<Duration> {
+        let frame = Duration::from_nanos(FRAME_NANOS);
+        match self.speed {
+            Speed::Normal => Some(frame),
+            Speed::Turbo => None,
+            Speed::SlowMotion => Some(frame * SLOW_MOTION_FACTOR),
+        }
+    }
+
+    /// Waits until it's time for the next frame. Deadlines are kept on a
+    /// fixed grid so that small oversleeps don't add up
+    pub fn wait(&mut self) {
+        let frame = match self.frame_duration() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        self.deadline += frame;
+        let now = Instant::now();
+        if now > self.deadline + frame * MAX_LAG_FRAMES {
+            self.deadline = now;
+            return;
+        }
+
+        if self.deadline > now + SPIN_TIME {
+            thread::sleep(self.deadline - now - SPIN_TIME);
+        }
+        while Instant::now() < self.deadline {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle() {
+        let mut pacer = FramePacer::new();
+
+        pacer.toggle(Speed::Turbo);
+        assert_eq!(pacer.speed(), Speed::Turbo);
+        assert_eq!(pacer.frame_duration(), None);
+        pacer.toggle(Speed::SlowMotion);
+        assert_eq!(pacer.speed(), Speed::SlowMotion);
+        assert_eq!(pacer.frame_duration(), Some(Duration::from_millis(66)));
+        pacer.toggle(Speed::SlowMotion);
+        assert_eq!(pacer.speed(), Speed::Normal);
+    }
+
+    #[test]
+    fn test_wait() {
+        // before the pacer takes its first deadline, so the frames can't
+        // look shorter than they were
+        let start = Instant::now();
+        let mut pacer = FramePacer::new();
+        for _ in 0..3 {
+            pacer.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_nanos(FRAME_NANOS * 3));
+
+        pacer.toggle(Speed::Turbo);
+        let start = Instant::now();
+        for _ in 0..100 {
+            pacer.wait();
+        }
+        assert!(start.elapsed() < Duration::from_nanos(FRAME_NANOS));
+    }
+}
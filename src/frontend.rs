@@ -0,0 +1,26 @@
+use display::Display;
+use input::Input;
+
+/// Where the machine's picture and sound go and its input comes from
+///
+/// PacmanSystem::run drives a frontend once a frame: poll_input, then, unless
+/// the frontend wants to quit, the frame is emulated and handed to present,
+/// queue_audio and end_frame in that order.
+pub trait Frontend {
+    /// Updates the pressed buttons from whatever the frontend reads input from
+    fn poll_input(&mut self, input: &mut Input);
+
+    /// Whether the user asked to stop, checked after every poll_input
+    fn should_quit(&self) -> bool;
+
+    /// Shows a finished frame. An error stops the machine
+    fn present(&mut self, display: &Display) -> Result<(), String>;
+
+    /// Takes the sound generated during the frame, mono at
+    /// sound::SAMPLE_RATE
+    fn queue_audio(&mut self, samples: &[i16]);
+
+    /// Called once the frame has been handed over, for frontends that keep
+    /// the machine to real time
+    fn end_frame(&mut self) {}
+}
@@ -0,0 +1,165 @@
+use rom::Roms;
+
+// where each 8x4 slice of a tile or sprite goes, in the order they're stored
+const TILE_SLICES: [(usize, usize); 2] = [(0, 4), (0, 0)];
+const SPRITE_SLICES: [(usize, usize); 8] = [
+    (8, 12),
+    (8, 0),
+    (8, 4),
+    (8, 8),
+    (0, 12),
+    (0, 0),
+    (0, 4),
+    (0, 8),
+];
+
+const TILES: usize = 256;
+const SPRITES: usize = 64;
+const PALETTES: usize = 64;
+// no flip, x flip, y flip and both
+const FLIPS: usize = 4;
+
+/// A tile or sprite decoded and coloured with one palette, ready to be
+/// copied into the frame buffer
+pub struct Graphic {
+    /// Width and height, 8 for tiles and 16 for sprites
+    pub size: usize,
+    /// RGB24 rows of size pixels
+    pub pixels: Vec<u8>,
+    /// False where the pixel is colour 0, which sprites leave transparent
+    pub opaque: Vec<bool>,
+}
+
+/// Every tile and sprite in every palette and orientation, decoded the first
+/// time it's drawn
+///
+/// The cache is tied to the Roms it was made from, and since they're only
+/// ever borrowed immutably it never needs invalidating. New ROMs get a new
+/// cache.
+pub struct GraphicsCache<'a> {
+    roms: &'a Roms,
+    tiles: Vec<Option<Graphic>>,
+    sprites: Vec<Option<Graphic>>,
+}
+
+impl<'a> GraphicsCache<'a> {
+    pub fn new(roms: &'a Roms) -> Self {
+        GraphicsCache {
+            roms: roms,
+            tiles: (0..TILES * PALETTES * FLIPS).map(|_| None).collect(),
+            sprites: (0..SPRITES * PALETTES * FLIPS).map(|_| None).collect(),
+        }
+    }
+
+    pub fn tile(&mut self, tile: usize, palette: usize, x_flip: bool, y_flip: bool) -> &Graphic {
+        let entry = cache_index(tile, palette, x_flip, y_flip);
+        if self.tiles[entry].is_none() {
+            self.tiles[entry] = Some(decode(self.roms, false, tile, palette, x_flip, y_flip));
+        }
+        self.tiles[entry].as_ref().unwrap()
+    }
+
+    pub fn sprite(
+        &mut self,
+        sprite: usize,
+        palette: usize,
+        x_flip: bool,
+        y_flip: bool,
+    ) -> &Graphic {
+        let entry = cache_index(sprite, palette, x_flip, y_flip);
+        if self.sprites[entry].is_none() {
+            self.sprites[entry] = Some(decode(self.roms, true, sprite, palette, x_flip, y_flip));
+        }
+        self.sprites[entry].as_ref().unwrap()
+    }
+}
+
+fn cache_index(number: usize, palette: usize, x_flip: bool, y_flip: bool) -> usize {
+    let flips = x_flip as usize | (y_flip as usize) << 1;
+    (number * PALETTES + palette) * FLIPS + flips
+}
+
+// colours one tile or sprite. each slice is stored a column of 4 pixels at a
+// time, starting from the bottom right
+fn decode(
+    roms: &Roms,
+    sprite: bool,
+    number: usize,
+    palette: usize,
+    x_flip: bool,
+    y_flip: bool,
+) -> Graphic {
+    let (size, slices, video_rom): (usize, &[(usize, usize)], &[u8]) = if sprite {
+        (16, &SPRITE_SLICES, &roms.sprite_rom)
+    } else {
+        (8, &TILE_SLICES, &roms.tile_rom)
+    };
+    let palette = &roms.palette_rom[palette];
+
+    let mut graphic = Graphic {
+        size: size,
+        pixels: vec![0; size * size * 3],
+        opaque: vec![false; size * size],
+    };
+
+    let mut index = number * size * size;
+    for &(slice_x, slice_y) in slices {
+        // flipping mirrors where the slice goes as well as its pixels
+        let slice_x = if x_flip { size - 8 - slice_x } else { slice_x };
+        let slice_y = if y_flip { size - 4 - slice_y } else { slice_y };
+
+        for x_offset in (0..8).rev() {
+            for y_offset in (0..4).rev() {
+                let color_index = palette[video_rom[index] as usize];
+                index += 1;
+
+                let x = slice_x + if x_flip { 7 - x_offset } else { x_offset };
+                let y = slice_y + if y_flip { 3 - y_offset } else { y_offset };
+                let pixel = y * size + x;
+                let color = roms.color_rom[color_index];
+                let rgb = [color.r, color.g, color.b];
+                graphic.pixels[pixel * 3..pixel * 3 + 3].copy_from_slice(&rgb);
+                graphic.opaque[pixel] = color_index != 0;
+            }
+        }
+    }
+
+    graphic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use display::Color;
+
+    #[test]
+    fn test_decode() {
+        let mut roms = Roms::new();
+        roms.color_rom[3] = Color::rgb(1, 2, 3);
+        roms.palette_rom[5] = [0, 0, 3, 0];
+        // the last pixel stored in a tile's top half is its top left
+        roms.tile_rom[64 + 63] = 2;
+
+        let mut cache = GraphicsCache::new(&roms);
+        let tile = cache.tile(1, 5, false, false);
+        assert_eq!(tile.size, 8);
+        assert_eq!(&tile.pixels[..3], &[1, 2, 3]);
+        assert!(tile.opaque[0]);
+        assert!(!tile.opaque[1]);
+
+        let tile = cache.tile(1, 5, true, true);
+        assert_eq!(&tile.pixels[63 * 3..], &[1, 2, 3]);
+        assert!(tile.opaque[63]);
+    }
+
+    #[test]
+    fn test_cached() {
+        let roms = Roms::new();
+        let mut cache = GraphicsCache::new(&roms);
+
+        let first = cache.sprite(3, 7, true, false) as *const Graphic;
+        let second = cache.sprite(3, 7, true, false) as *const Graphic;
+        assert_eq!(first, second);
+        assert!(cache.sprites[cache_index(3, 7, false, false)].is_none());
+    }
+}
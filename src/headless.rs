@@ -0,0 +1,105 @@
+use display::Display;
+use frontend::Frontend;
+use input::Input;
+use pacman::PacmanSystem;
+use screenshot::{self, ImageFormat};
+
+use std::path::PathBuf;
+
+/// Exit status of a headless run in which the watchdog had to reset the
+/// game, which means it hung
+pub const WATCHDOG_STATUS: i32 = 2;
+
+/// What to run and which frames to save
+pub struct HeadlessOptions {
+    pub frames: u32,
+    /// Frame numbers, counting from 1, to save screenshots of
+    pub dump_frames: Vec<u32>,
+    pub dump_dir: PathBuf,
+    pub format: ImageFormat,
+}
+
+impl HeadlessOptions {
+    pub fn new() -> Self {
+        HeadlessOptions {
+            frames: 0,
+            dump_frames: Vec::new(),
+            dump_dir: PathBuf::from("."),
+            format: ImageFormat::Png,
+        }
+    }
+}
+
+/// Runs <pacman> for the requested number of frames as fast as possible,
+/// with no window, sound or input, saving the requested frames as
+/// frame-<n>.ppm or .png
+///
+/// Returns the status to exit with: 0, or WATCHDOG_STATUS if the game hung.
+pub fn run(pacman: &mut PacmanSystem, options: &HeadlessOptions) -> Result<i32, String> {
+    pacman.run(&mut Headless {
+        options: options,
+        frame: 0,
+    })?;
+
+    if pacman.watchdog_resets() > 0 {
+        Ok(WATCHDOG_STATUS)
+    } else {
+        Ok(0)
+    }
+}
+
+// counts frames and saves the ones asked for
+struct Headless<'o> {
+    options: &'o HeadlessOptions,
+    frame: u32,
+}
+
+impl<'o> Frontend for Headless<'o> {
+    fn poll_input(&mut self, _: &mut Input) {}
+
+    fn should_quit(&self) -> bool {
+        self.frame >= self.options.frames
+    }
+
+    fn present(&mut self, display: &Display) -> Result<(), String> {
+        self.frame += 1;
+        if self.options.dump_frames.contains(&self.frame) {
+            let file_name = format!("frame-{}.{}", self.frame, self.options.format.extension());
+            let path = self.options.dump_dir.join(file_name);
+            screenshot::save(display, &path, self.options.format)
+                .map_err(|why| format!("Could not write {}: {}", path.display(), why))?;
+        }
+        Ok(())
+    }
+
+    // nobody is listening
+    fn queue_audio(&mut self, _: &[i16]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rom::Roms;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_run() {
+        // a ROM of NOPs never resets the watchdog
+        let roms = Box::new(Roms::new());
+        let mut pacman = PacmanSystem::new(&roms);
+        let mut options = HeadlessOptions::new();
+        options.frames = 20;
+        options.dump_frames = vec![3];
+        options.dump_dir = env::temp_dir().join("pacman-headless-test");
+        options.format = ImageFormat::Ppm;
+        fs::create_dir_all(&options.dump_dir).unwrap();
+
+        let status = run(&mut pacman, &options);
+        let dumped = fs::metadata(options.dump_dir.join("frame-3.ppm")).map(|file| file.len());
+        fs::remove_dir_all(&options.dump_dir).unwrap();
+
+        assert_eq!(status, Ok(WATCHDOG_STATUS));
+        assert!(dumped.unwrap() > 0);
+    }
+}
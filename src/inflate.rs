@@ -0,0 +1,300 @@
+// a decoder for raw deflate streams (RFC 1951), which is how zip archives
+// compress their files. written for clarity rather than speed, ROMs are
+// small
+
+const MAX_BITS: usize = 15;
+
+// base lengths and extra bits of length codes 257-285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// base distances and extra bits of distance codes 0-29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// the order code length code lengths are sent in by dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a raw deflate stream, with no zlib or gzip header
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut input = BitReader {
+        data: data,
+        position: 0,
+        bit: 0,
+    };
+    let mut output = Vec::new();
+
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => stored_block(&mut input, &mut output)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                compressed_block(&mut input, &mut output, &literals, &distances)?
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut input)?;
+                compressed_block(&mut input, &mut output, &literals, &distances)?
+            }
+            _ => return Err("Invalid deflate block type".to_string()),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+// deflate packs bits starting from the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    // bits of data[position] already read
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| "Deflate stream is truncated".to_string())?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+    }
+}
+
+// a canonical Huffman code, stored as how many codes there are of each
+// length and the symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..MAX_BITS + 1 {
+            for (symbol, _) in lengths
+                .iter()
+                .enumerate()
+                .filter(|&(_, l)| *l as usize == length)
+            {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman {
+            counts: counts,
+            symbols: symbols,
+        }
+    }
+
+    // reads a code a bit at a time, codes of each length follow on from the
+    // last code of the length before
+    fn decode(&self, input: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..MAX_BITS + 1 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code".to_string())
+    }
+}
+
+fn stored_block(input: &mut BitReader, output: &mut Vec<u8>) -> Result<(), String> {
+    input.align();
+    let length = input.bits(16)?;
+    let complement = input.bits(16)?;
+    if length != !complement & 0xFFFF {
+        return Err("Stored block length is corrupt".to_string());
+    }
+
+    let start = input.position;
+    let end = start + length as usize;
+    if end > input.data.len() {
+        return Err("Deflate stream is truncated".to_string());
+    }
+    output.extend_from_slice(&input.data[start..end]);
+    input.position = end;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+// the block's literal/length and distance codes, themselves Huffman coded
+fn dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_length_count = input.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("Too many codes in a dynamic block".to_string());
+    }
+
+    let mut code_length_lengths = [0; 19];
+    for i in 0..code_length_count {
+        code_length_lengths[CODE_LENGTH_ORDER[i]] = input.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(input)? {
+            symbol @ 0...15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| "Repeated code length with nothing before it".to_string())?;
+                (previous, 3 + input.bits(2)?)
+            }
+            17 => (0, 3 + input.bits(3)?),
+            _ => (0, 11 + input.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("Code lengths overrun the dynamic block's codes".to_string());
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn compressed_block(
+    input: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(input)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        // a copy of earlier output
+        let code = symbol - 257;
+        if code >= LENGTH_BASE.len() {
+            return Err("Invalid length code".to_string());
+        }
+        let length = LENGTH_BASE[code] as usize + input.bits(LENGTH_EXTRA[code])? as usize;
+        let code = distances.decode(input)? as usize;
+        if code >= DISTANCE_BASE.len() {
+            return Err("Invalid distance code".to_string());
+        }
+        let distance = DISTANCE_BASE[code] as usize + input.bits(DISTANCE_EXTRA[code])? as usize;
+        if distance > output.len() {
+            return Err("Distance reaches back before the start".to_string());
+        }
+
+        // the copy can overlap what it's writing
+        let start = output.len() - distance;
+        for i in 0..length {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::crc32;
+
+    #[test]
+    fn test_stored() {
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data), Ok(b"abc".to_vec()));
+        assert!(inflate(&data[..7]).is_err());
+    }
+
+    #[test]
+    fn test_fixed() {
+        let data = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01];
+        assert_eq!(inflate(&data), Ok(b"hello hello hello hello".to_vec()));
+    }
+
+    #[test]
+    fn test_dynamic() {
+        // zlib's compression of ((i * i) % 7 + i / 100) for i in 0..2000
+        let data = [
+            0xB5, 0xC5, 0xC9, 0x01, 0xC4, 0x30, 0x08, 0x04, 0xB0, 0x8D, 0x6F, 0x30, 0x60, 0x7C,
+            0x25, 0xFD, 0x77, 0xBA, 0x55, 0x8C, 0x3E, 0xFA, 0x3D, 0x29, 0x84, 0xF4, 0xFC, 0x90,
+            0xE5, 0x18, 0x73, 0x78, 0x02, 0xB4, 0x54, 0x62, 0x88, 0x25, 0x41, 0x4B, 0x31, 0xD5,
+            0x9C, 0x2B, 0xB4, 0xDC, 0x4A, 0x69, 0x39, 0x41, 0xAB, 0x95, 0x4A, 0x2E, 0x04, 0x8D,
+            0x6B, 0xA9, 0xDC, 0x1A, 0xB4, 0xDA, 0x3A, 0x51, 0x6F, 0xD0, 0x84, 0x59, 0xA8, 0x11,
+            0xB4, 0xAE, 0x4C, 0xAC, 0x1D, 0x5A, 0xE7, 0x6E, 0x22, 0x06, 0x4D, 0x86, 0xEA, 0x90,
+            0x0E, 0xCD, 0xCC, 0x55, 0xD4, 0xA1, 0x4D, 0x53, 0x9B, 0x63, 0x40, 0xB3, 0xB1, 0xDC,
+            0xD7, 0x80, 0xB6, 0xE7, 0xDC, 0x3E, 0x1C, 0xDA, 0x3A, 0xD3, 0xE7, 0x59, 0xD0, 0xD6,
+            0x5C, 0x77, 0xEF, 0x0B, 0x6D, 0xBF, 0xE7, 0xBC, 0x7B, 0x41, 0xBB, 0xF7, 0x3B, 0xFB,
+            0x7C, 0xD0, 0xFE,
+        ];
+        let output = inflate(&data).unwrap();
+        assert_eq!(output.len(), 2000);
+        assert_eq!(crc32(&output), 0xDC34_02AF);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(inflate(&[]).is_err());
+        // block type 3
+        assert!(inflate(&[0x07]).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+/// Every switch wired to the IN0 and IN1 ports
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    PlayerOneUp,
+    PlayerOneLeft,
+    PlayerOneRight,
+    PlayerOneDown,
+    RackTest,
+    CoinOne,
+    CoinTwo,
+    Service,
+    PlayerTwoUp,
+    PlayerTwoLeft,
+    PlayerTwoRight,
+    PlayerTwoDown,
+    Test,
+    StartOne,
+    StartTwo,
+}
+
+/// Cabinet type, reported to the game through bit 7 of IN1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cabinet {
+    // one joystick shared by both players
+    Upright,
+    // the players sit opposite each other with a joystick each, and the
+    // screen is flipped for player two's turns
+    Cocktail,
+}
+
+// all of the inputs are active low, so nothing pressed reads as all ones.
+// bit 7 of IN1 is the cabinet type, which is high for an upright
+const IN0_IDLE: u8 = 0xFF;
+const IN1_IDLE: u8 = 0xFF;
+const CABINET_BIT: u8 = 1 << 7;
+
+/// State of the IN0 (0x5000) and IN1 (0x5040) ports
+///
+/// IN0: bits 0-3 player one up/left/right/down, 4 rack test, 5 coin one,
+/// 6 coin two, 7 service credit
+///
+/// IN1: bits 0-3 player two up/left/right/down, 4 test switch, 5 start one,
+/// 6 start two, 7 cabinet type
+pub struct Input {
+    in0: u8,
+    in1: u8,
+    cabinet: Cabinet,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            in0: IN0_IDLE,
+            in1: IN1_IDLE,
+            cabinet: Cabinet::Upright,
+        }
+    }
+
+    pub fn set_cabinet(&mut self, cabinet: Cabinet) {
+        self.cabinet = cabinet;
+        match cabinet {
+            Cabinet::Upright => self.in1 |= CABINET_BIT,
+            Cabinet::Cocktail => self.in1 &= !CABINET_BIT,
+        }
+    }
+
+    pub fn in0(&self) -> u8 {
+        match self.cabinet {
+            // an upright only has player one's joystick, so player two's
+            // controls move it too
+            Cabinet::Upright => self.in0 & (self.in1 | 0xF0),
+            Cabinet::Cocktail => self.in0,
+        }
+    }
+
+    pub fn in1(&self) -> u8 {
+        self.in1
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.set(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set(button, false);
+    }
+
+    /// Sets <button> to pressed (pulling its bit low) or released
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let (port, bit) = match button {
+            Button::PlayerOneUp => (&mut self.in0, 0),
+            Button::PlayerOneLeft => (&mut self.in0, 1),
+            Button::PlayerOneRight => (&mut self.in0, 2),
+            Button::PlayerOneDown => (&mut self.in0, 3),
+            Button::RackTest => (&mut self.in0, 4),
+            Button::CoinOne => (&mut self.in0, 5),
+            Button::CoinTwo => (&mut self.in0, 6),
+            Button::Service => (&mut self.in0, 7),
+            Button::PlayerTwoUp => (&mut self.in1, 0),
+            Button::PlayerTwoLeft => (&mut self.in1, 1),
+            Button::PlayerTwoRight => (&mut self.in1, 2),
+            Button::PlayerTwoDown => (&mut self.in1, 3),
+            Button::Test => (&mut self.in1, 4),
+            Button::StartOne => (&mut self.in1, 5),
+            Button::StartTwo => (&mut self.in1, 6),
+        };
+
+        if pressed {
+            *port &= !(1 << bit);
+        } else {
+            *port |= 1 << bit;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle() {
+        let input = Input::new();
+
+        assert_eq!(input.in0(), 0xFF);
+        assert_eq!(input.in1(), 0xFF);
+    }
+
+    #[test]
+    fn test_press_release() {
+        let mut input = Input::new();
+
+        input.press(Button::CoinOne);
+        input.press(Button::PlayerOneLeft);
+        assert_eq!(input.in0(), 0b1101_1101);
+        input.release(Button::CoinOne);
+        assert_eq!(input.in0(), 0b1111_1101);
+
+        input.press(Button::StartTwo);
+        assert_eq!(input.in1(), 0b1011_1111);
+    }
+
+    #[test]
+    fn test_cabinet() {
+        let mut input = Input::new();
+
+        input.press(Button::PlayerTwoDown);
+        assert_eq!(input.in0(), 0b1111_0111);
+        assert_eq!(input.in1(), 0b1111_0111);
+
+        input.set_cabinet(Cabinet::Cocktail);
+        assert_eq!(input.in0(), 0xFF);
+        assert_eq!(input.in1(), 0b0111_0111);
+
+        input.set_cabinet(Cabinet::Upright);
+        assert_eq!(input.in1(), 0b1111_0111);
+    }
+}
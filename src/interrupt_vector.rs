@@ -1,5 +1,7 @@
 use cpu::io::InputOutput;
 
+/// Latch holding the low byte of the IM 2 vector, which the game sets with
+/// OUT (0),A. The board doesn't decode the port number, so any port works
 pub struct InterruptVector {
     pub data: u8,
 }
@@ -0,0 +1,47 @@
+//! A Pac-Man arcade machine emulator
+//!
+//! The Z80 core in `cpu` runs on its own against anything implementing
+//! `Memory` and `InputOutput`, while `PacmanSystem` puts it together with the
+//! rest of the board: ROMs, video, the WSG sound chip, inputs and DIP
+//! switches. `PacmanSystem::run` plays it through any `Frontend`; with the
+//! `sdl` feature (on by default) `sdl_frontend` runs the machine in a window,
+//! and `headless` runs it without one.
+
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+
+#[cfg(feature = "sdl")]
+mod audio;
+mod checksum;
+pub mod cpu;
+pub mod dip_switches;
+pub mod display;
+#[cfg(feature = "sdl")]
+mod frame_pacer;
+pub mod frontend;
+mod graphics_cache;
+pub mod headless;
+mod inflate;
+pub mod input;
+mod interrupt_vector;
+#[cfg(feature = "libretro")]
+mod libretro;
+pub mod memory_map;
+pub mod memory_mapper;
+pub mod pacman;
+pub mod rom;
+pub mod rom_database;
+mod savestate;
+pub mod screenshot;
+#[cfg(feature = "sdl")]
+pub mod sdl_frontend;
+pub mod sound;
+mod zip;
+
+pub use cpu::io::InputOutput;
+pub use cpu::mem::Memory;
+pub use cpu::Z80;
+pub use frontend::Frontend;
+pub use memory_mapper::MemoryMapper;
+pub use pacman::PacmanSystem;
+pub use rom::Roms;
@@ -0,0 +1,468 @@
+// the libretro core's entry points, so that the machine can be loaded by
+// RetroArch or any other libretro host. content is a MAME style zip of the
+// set, or any file in the ROM directory, pacman.6e for example, and the
+// whole set is loaded from the directory it's in
+
+use display::{Display, HEIGHT, WIDTH};
+use frontend::Frontend;
+use input::{Button, Input};
+use pacman::PacmanSystem;
+use rom::{self, Roms};
+use sound::SAMPLE_RATE;
+
+use std::cell::{Cell, RefCell};
+use std::ffi::CStr;
+use std::mem::ManuallyDrop;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+const RETRO_DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+
+// 3.072 MHz over 264 lines of 192 cycles
+const FPS: f64 = 3_072_000.0 / 50_688.0;
+
+// select inserts a coin and start starts a game for the pad's player
+const JOYPAD_BUTTONS: [[(c_uint, Button); 6]; 2] = [
+    [
+        (RETRO_DEVICE_ID_JOYPAD_UP, Button::PlayerOneUp),
+        (RETRO_DEVICE_ID_JOYPAD_DOWN, Button::PlayerOneDown),
+        (RETRO_DEVICE_ID_JOYPAD_LEFT, Button::PlayerOneLeft),
+        (RETRO_DEVICE_ID_JOYPAD_RIGHT, Button::PlayerOneRight),
+        (RETRO_DEVICE_ID_JOYPAD_START, Button::StartOne),
+        (RETRO_DEVICE_ID_JOYPAD_SELECT, Button::CoinOne),
+    ],
+    [
+        (RETRO_DEVICE_ID_JOYPAD_UP, Button::PlayerTwoUp),
+        (RETRO_DEVICE_ID_JOYPAD_DOWN, Button::PlayerTwoDown),
+        (RETRO_DEVICE_ID_JOYPAD_LEFT, Button::PlayerTwoLeft),
+        (RETRO_DEVICE_ID_JOYPAD_RIGHT, Button::PlayerTwoRight),
+        (RETRO_DEVICE_ID_JOYPAD_START, Button::StartTwo),
+        (RETRO_DEVICE_ID_JOYPAD_SELECT, Button::CoinTwo),
+    ],
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+pub type RetroEnvironment = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh =
+    extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = extern "C" fn();
+pub type RetroInputState =
+    extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+// what the host handed us through the retro_set_* functions
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+// the loaded game. PacmanSystem borrows the ROMs, which are owned here and
+// freed after it
+struct Core {
+    pacman: ManuallyDrop<PacmanSystem<'static>>,
+    roms: *mut Roms,
+    // the picture converted to XRGB8888
+    frame: Vec<u32>,
+}
+
+impl Core {
+    fn new(roms: Box<Roms>) -> Self {
+        let roms = Box::into_raw(roms);
+        Core {
+            pacman: ManuallyDrop::new(PacmanSystem::new(unsafe { &*roms })),
+            roms: roms,
+            frame: vec![0; WIDTH * HEIGHT],
+        }
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.pacman);
+            drop(Box::from_raw(self.roms));
+        }
+    }
+}
+
+// libretro calls into the core from a single thread
+thread_local! {
+    static CALLBACKS: Cell<Callbacks> = Cell::new(Callbacks {
+        environment: None,
+        video_refresh: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+    });
+    static CORE: RefCell<Option<Core>> = RefCell::new(None);
+}
+
+fn callbacks() -> Callbacks {
+    CALLBACKS.with(|callbacks| callbacks.get())
+}
+
+fn set_callbacks<F: FnOnce(&mut Callbacks)>(set: F) {
+    CALLBACKS.with(|callbacks| {
+        let mut new = callbacks.get();
+        set(&mut new);
+        callbacks.set(new);
+    });
+}
+
+// runs <f> on the loaded game, or returns <default> if there isn't one.
+// calls back into the core from the host's callbacks during retro_run
+// aren't supported, they find the game in use and get <default> too rather
+// than panicking across the FFI boundary
+fn with_core<T, F: FnOnce(&mut Core) -> T>(default: T, f: F) -> T {
+    CORE.with(|core| match core.try_borrow_mut() {
+        Ok(mut core) => match *core {
+            Some(ref mut core) => f(core),
+            None => default,
+        },
+        Err(_) => default,
+    })
+}
+
+// replaces the loaded game, returning false if it's in use, see with_core
+fn set_core(new: Option<Core>) -> bool {
+    CORE.with(|core| match core.try_borrow_mut() {
+        Ok(mut core) => {
+            *core = new;
+            true
+        }
+        Err(_) => false,
+    })
+}
+
+// the host, as seen from PacmanSystem::run_once
+struct Host<'a> {
+    callbacks: Callbacks,
+    frame: &'a mut Vec<u32>,
+}
+
+impl<'a> Frontend for Host<'a> {
+    fn poll_input(&mut self, input: &mut Input) {
+        if let Some(poll) = self.callbacks.input_poll {
+            poll();
+        }
+        let state = match self.callbacks.input_state {
+            Some(state) => state,
+            None => return,
+        };
+
+        for (port, buttons) in JOYPAD_BUTTONS.iter().enumerate() {
+            for &(id, button) in buttons.iter() {
+                input.set(
+                    button,
+                    state(port as c_uint, RETRO_DEVICE_JOYPAD, 0, id) != 0,
+                );
+            }
+        }
+    }
+
+    // the host decides when to stop
+    fn should_quit(&self) -> bool {
+        false
+    }
+
+    fn present(&mut self, display: &Display) -> Result<(), String> {
+        for (pixel, rgb) in self.frame.iter_mut().zip(display.pixels().chunks(3)) {
+            *pixel = (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+        if let Some(refresh) = self.callbacks.video_refresh {
+            refresh(
+                self.frame.as_ptr() as *const c_void,
+                WIDTH as c_uint,
+                HEIGHT as c_uint,
+                WIDTH * 4,
+            );
+        }
+        Ok(())
+    }
+
+    // the WSG is mono, libretro wants interleaved stereo
+    fn queue_audio(&mut self, samples: &[i16]) {
+        let batch = match self.callbacks.audio_sample_batch {
+            Some(batch) => batch,
+            None => return,
+        };
+
+        let mut stereo = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            stereo.push(*sample);
+            stereo.push(*sample);
+        }
+        let mut frames = samples.len();
+        let mut offset = 0;
+        while frames > 0 {
+            let taken = batch(stereo[offset * 2..].as_ptr(), frames).min(frames);
+            if taken == 0 {
+                break;
+            }
+            offset += taken;
+            frames -= taken;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: RetroEnvironment) {
+    set_callbacks(|callbacks| callbacks.environment = Some(environment));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: RetroVideoRefresh) {
+    set_callbacks(|callbacks| callbacks.video_refresh = Some(video_refresh));
+}
+
+// every frame's sound goes out in one batch, so single samples aren't used
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: RetroAudioSampleBatch) {
+    set_callbacks(|callbacks| callbacks.audio_sample_batch = Some(audio_sample_batch));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: RetroInputPoll) {
+    set_callbacks(|callbacks| callbacks.input_poll = Some(input_poll));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: RetroInputState) {
+    set_callbacks(|callbacks| callbacks.input_state = Some(input_state));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    set_core(None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: b"Pacman\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"zip|6e\0".as_ptr() as *const c_char,
+        need_fullpath: true,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+// there's only the joypad
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_: c_uint, _: c_uint) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).path.is_null() {
+        return false;
+    }
+    let path = CStr::from_ptr((*game).path).to_string_lossy().into_owned();
+    let path = Path::new(&path);
+    let is_zip = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("zip"));
+    let directory = if path.is_dir() || is_zip {
+        path
+    } else {
+        path.parent().unwrap_or(Path::new("."))
+    };
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    let environment = match callbacks().environment {
+        Some(environment) => environment,
+        None => return false,
+    };
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut c_uint as *mut c_void,
+    ) {
+        eprintln!("The host doesn't support XRGB8888 video");
+        return false;
+    }
+
+    let set = rom::detect_set(directory);
+    let roms = match Roms::load(directory, set) {
+        Ok(roms) => Box::new(roms),
+        Err(report) => {
+            eprintln!(
+                "Couldn't load the {} ROMs in {}:",
+                set.description,
+                directory.display()
+            );
+            eprint!("{}", report);
+            return false;
+        }
+    };
+    set_core(Some(Core::new(roms)))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_: c_uint, _: *const RetroGameInfo, _: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    set_core(None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| core.pacman.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core((), |core| {
+        let mut host = Host {
+            callbacks: callbacks(),
+            frame: &mut core.frame,
+        };
+        if let Err(why) = core.pacman.run_once(&mut host) {
+            eprintln!("{}", why);
+        }
+    });
+}
+
+// states are always the same size, so the host can ask before saving one
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.pacman.save_state().len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        let state = core.pacman.save_state();
+        if data.is_null() || size < state.len() {
+            return false;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core| {
+        // a bad state can leave the machine half restored
+        let backup = core.pacman.save_state();
+        match core.pacman.load_state(state) {
+            Ok(()) => true,
+            Err(why) => {
+                eprintln!("Couldn't load state: {}", why);
+                // the backup was just made, so it always loads
+                let _ = core.pacman.load_state(&backup);
+                false
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_: c_uint, _: bool, _: *const c_char) {}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_: c_uint) -> usize {
+    0
+}
@@ -1,36 +1,364 @@
-mod cpu;
-mod display;
-mod interrupt_vector;
-mod memory_map;
-mod memory_mapper;
-mod pacman;
-mod rom;
-
-#[macro_use(matches)]
-extern crate matches;
-extern crate sdl2;
-
-use pacman::PacmanSystem;
-use rom::Roms;
+extern crate pacman;
+
+use pacman::dip_switches::{self, DipSwitches};
+use pacman::display::VideoSettings;
+use pacman::headless::{self, HeadlessOptions};
+use pacman::input::Cabinet;
+use pacman::rom::{self, Verification};
+use pacman::rom_database::{self, RomSet};
+use pacman::screenshot::ImageFormat;
+#[cfg(feature = "sdl")]
+use pacman::sdl_frontend;
+use pacman::sound::AudioMode;
+use pacman::{PacmanSystem, Roms};
 use std::boxed::Box;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+
+struct Options {
+    directory: Option<String>,
+    set: Option<&'static RomSet>,
+    debug: bool,
+    verify: bool,
+    dip_switches: DipSwitches,
+    audio: AudioMode,
+    cabinet: Cabinet,
+    watchdog: bool,
+    video: VideoSettings,
+    headless: Option<HeadlessOptions>,
+}
+
+// usage: pacman [rom directory or zip] [--set name] [--verify] [--debug]
+//               [--mute | --no-sound]
+//               [--cocktail] [--no-watchdog] [--scale n] [--stretch]
+//               [--fullscreen] [--config file] [--<dip switch> value]...
+//               [--headless frames [--dump-frames n,n...] [--dump-dir dir]
+//                [--dump-format png|ppm]]
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        directory: None,
+        set: None,
+        debug: false,
+        verify: false,
+        dip_switches: DipSwitches::new(),
+        audio: AudioMode::Enabled,
+        cabinet: Cabinet::Upright,
+        watchdog: true,
+        video: VideoSettings::new(),
+        headless: None,
+    };
+    let mut headless = HeadlessOptions::new();
+    let mut run_headless = false;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.directory = Some(arg.clone());
+            continue;
+        }
+
+        let name = &arg[2..];
+        match name {
+            "set" => {
+                let value = next_value(&mut args, arg)?;
+                let set = rom_database::find_set(value).ok_or_else(|| {
+                    let names: Vec<&str> = rom_database::SETS.iter().map(|set| set.name).collect();
+                    format!(
+                        "Unknown ROM set: {}, the known sets are {}",
+                        value,
+                        names.join(", ")
+                    )
+                })?;
+                options.set = Some(set);
+            }
+            "debug" => options.debug = true,
+            "verify" => options.verify = true,
+            "mute" => options.audio = AudioMode::Muted,
+            "no-sound" => options.audio = AudioMode::Disabled,
+            "cocktail" => options.cabinet = Cabinet::Cocktail,
+            "no-watchdog" => options.watchdog = false,
+            "scale" => {
+                let value = next_value(&mut args, arg)?;
+                options.video.scale = match value.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("Invalid scale: {}", value)),
+                };
+            }
+            "stretch" => options.video.integer_scaling = false,
+            "fullscreen" => options.video.fullscreen = true,
+            "headless" => {
+                let value = next_value(&mut args, arg)?;
+                headless.frames = value
+                    .parse()
+                    .map_err(|_| format!("Invalid frame count: {}", value))?;
+                run_headless = true;
+            }
+            "dump-frames" => {
+                let value = next_value(&mut args, arg)?;
+                for frame in value.split(',') {
+                    let frame = frame
+                        .parse()
+                        .map_err(|_| format!("Invalid frame number: {}", frame))?;
+                    headless.dump_frames.push(frame);
+                }
+            }
+            "dump-dir" => headless.dump_dir = PathBuf::from(next_value(&mut args, arg)?),
+            "dump-format" => {
+                let value = next_value(&mut args, arg)?;
+                headless.format = ImageFormat::from_name(value)
+                    .ok_or_else(|| format!("Unknown image format: {}", value))?;
+            }
+            "config" => {
+                let path = next_value(&mut args, arg)?;
+                options.dip_switches.load_config(Path::new(path))?;
+            }
+            _ if dip_switches::SETTINGS.contains(&name) => {
+                let value = next_value(&mut args, arg)?;
+                options.dip_switches.set(name, value)?;
+            }
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+
+    if run_headless {
+        options.headless = Some(headless);
+    }
+    Ok(options)
+}
+
+fn next_value<'a, I: Iterator<Item = &'a String>>(
+    args: &mut I,
+    option: &str,
+) -> Result<&'a String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}", option))
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(why) => {
+            eprintln!("{}", why);
+            process::exit(1);
+        }
+    };
 
     let current_dir = env::current_dir().unwrap();
-    let directory = match args.get(1) {
-        Some(dir) => Path::new(dir),
+    let directory = match options.directory {
+        Some(ref dir) => Path::new(dir),
         None => current_dir.as_path(),
     };
 
-    let rom = Box::new(Roms::load(&directory));
+    let set = options.set.unwrap_or_else(|| rom::detect_set(directory));
+
+    if options.verify {
+        process::exit(verify(directory, set));
+    }
+
+    let rom = match Roms::load(directory, set) {
+        Ok(roms) => Box::new(roms),
+        Err(report) => {
+            eprintln!(
+                "Couldn't load the {} ROMs in {}:",
+                set.description,
+                directory.display()
+            );
+            eprint!("{}", report);
+            process::exit(1);
+        }
+    };
+    warn_if_unverified(directory, set);
     let mut pacman = PacmanSystem::new(&rom);
+    pacman.set_dip_switches(options.dip_switches);
+    pacman.set_cabinet(options.cabinet);
+    pacman.set_watchdog(options.watchdog);
+
+    let status = if options.debug {
+        match pacman.debug() {
+            Ok(()) => 0,
+            Err(why) => {
+                eprintln!("{}", why);
+                1
+            }
+        }
+    } else {
+        match options.headless {
+            Some(ref headless) => match headless::run(&mut pacman, headless) {
+                Ok(status) => status,
+                Err(why) => {
+                    eprintln!("{}", why);
+                    1
+                }
+            },
+            None => run_windowed(&mut pacman, options.video, options.audio),
+        }
+    };
+    process::exit(status);
+}
+
+// prints how each file compares with the known good dumps, and returns the
+// status to exit with: 0 unless some are bad
+fn verify(directory: &Path, set: &RomSet) -> i32 {
+    println!("{} ({})", set.description, set.name);
+    let verified = rom::verify(directory, set);
+    for &(file_name, ref verification) in verified.iter() {
+        println!("{}: {}", file_name, verification);
+    }
+
+    let (summary, status) = verify_summary(directory, &verified);
+    println!("{}", summary);
+    status
+}
+
+// the last line of a --verify run and the status to exit with. files that
+// are only unknown don't fail it, but the set can't be called good either
+fn verify_summary(directory: &Path, verified: &[(&str, Verification)]) -> (String, i32) {
+    let bad = files_where(verified, |verification| {
+        matches!(*verification, Verification::Bad(_))
+    });
+    let unknown = files_where(verified, |verification| {
+        matches!(*verification, Verification::Unknown)
+    });
 
-    if args.contains(&String::from("--debug")) {
-        pacman.debug();
+    if !bad.is_empty() {
+        let summary = format!(
+            "{} of the ROMs in {} are bad",
+            bad.len(),
+            directory.display()
+        );
+        (summary, 1)
+    } else if !unknown.is_empty() {
+        let summary = format!(
+            "The ROM set in {} could not be fully verified ({})",
+            directory.display(),
+            unknown.join(", ")
+        );
+        (summary, 0)
     } else {
-        pacman.start();
+        (format!("The ROM set in {} is good", directory.display()), 0)
+    }
+}
+
+fn warn_if_unverified(directory: &Path, set: &RomSet) {
+    for warning in unverified_warnings(&rom::verify(directory, set)) {
+        eprintln!("{}", warning);
+    }
+}
+
+// what to warn about before running on <verified> ROMs
+fn unverified_warnings(verified: &[(&str, Verification)]) -> Vec<String> {
+    let bad = files_where(verified, |verification| {
+        matches!(*verification, Verification::Bad(_))
+    });
+    let unknown = files_where(verified, |verification| {
+        matches!(*verification, Verification::Unknown)
+    });
+
+    let mut warnings = Vec::new();
+    if !bad.is_empty() {
+        warnings.push(format!(
+            "Warning: running on ROMs that don't match the known good dumps ({}), \
+             see --verify",
+            bad.join(", ")
+        ));
+    }
+    if !unknown.is_empty() {
+        warnings.push(format!(
+            "Warning: running on ROMs that could not be fully verified ({}), see --verify",
+            unknown.join(", ")
+        ));
+    }
+    warnings
+}
+
+fn files_where<'a, F: Fn(&Verification) -> bool>(
+    verified: &[(&'a str, Verification)],
+    predicate: F,
+) -> Vec<&'a str> {
+    verified
+        .iter()
+        .filter(|&&(_, ref verification)| predicate(verification))
+        .map(|&(file_name, _)| file_name)
+        .collect()
+}
+
+#[cfg(feature = "sdl")]
+fn run_windowed(pacman: &mut PacmanSystem, video: VideoSettings, audio: AudioMode) -> i32 {
+    match sdl_frontend::run(pacman, video, audio) {
+        Ok(()) => 0,
+        Err(why) => {
+            eprintln!("{}", why);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_windowed(_: &mut PacmanSystem, _: VideoSettings, _: AudioMode) -> i32 {
+    eprintln!("Built without the sdl feature, only --headless runs are available");
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pacman::rom::RomError;
+
+    fn verified() -> Vec<(&'static str, Verification)> {
+        vec![
+            ("pacman.6e", Verification::Good),
+            ("pacfast.6f", Verification::Unknown),
+            ("pacman.6h", Verification::Bad(RomError::Missing)),
+        ]
+    }
+
+    #[test]
+    fn test_verify_summary() {
+        let directory = Path::new("roms");
+        let mut verified = verified();
+        assert_eq!(
+            verify_summary(directory, &verified),
+            ("1 of the ROMs in roms are bad".to_string(), 1)
+        );
+
+        verified.pop();
+        assert_eq!(
+            verify_summary(directory, &verified),
+            (
+                "The ROM set in roms could not be fully verified (pacfast.6f)".to_string(),
+                0
+            )
+        );
+
+        verified.pop();
+        assert_eq!(
+            verify_summary(directory, &verified),
+            ("The ROM set in roms is good".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn test_unverified_warnings() {
+        let mut verified = verified();
+        assert_eq!(
+            unverified_warnings(&verified),
+            vec![
+                "Warning: running on ROMs that don't match the known good dumps (pacman.6h), \
+                 see --verify",
+                "Warning: running on ROMs that could not be fully verified (pacfast.6f), \
+                 see --verify",
+            ]
+        );
+
+        verified.pop();
+        assert_eq!(
+            unverified_warnings(&verified),
+            vec!["Warning: running on ROMs that could not be fully verified (pacfast.6f), see --verify"]
+        );
+
+        verified.pop();
+        assert!(unverified_warnings(&verified).is_empty());
     }
 }
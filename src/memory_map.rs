@@ -82,7 +82,7 @@ pub fn map_address(addr: u16, writing: bool) -> Result<Address, &'static str> {
         }
 
         (VRAM_PALETTES_START...VRAM_PALETTES_END, _) => {
-            Ok(Address::VramSprites((addr - VRAM_PALETTES_START) as usize))
+            Ok(Address::VramPalettes((addr - VRAM_PALETTES_START) as usize))
         }
 
         (RAM_START...RAM_END, _) => Ok(Address::Ram((addr - RAM_START) as usize)),
@@ -129,5 +129,11 @@ mod tests {
 
         let addr = map_address(0x4805, true);
         assert!(matches!(addr.unwrap(), Address::Ram(5)));
+
+        let addr = map_address(0x4402, true);
+        assert!(matches!(addr.unwrap(), Address::VramPalettes(2)));
+
+        let addr = map_address(0x4FF3, false);
+        assert!(matches!(addr.unwrap(), Address::VramSprites(3)));
     }
 }
@@ -1,25 +1,112 @@
 use cpu::mem::Memory;
-use display::Display;
+use dip_switches::DipSwitches;
+use display::{Display, HEIGHT, WIDTH};
+use input::Input;
 use memory_map::{map_address, Address};
 use rom::Roms;
+use savestate::{StateReader, StateWriter};
+use sound::Wsg;
+
+// 0x4800-0x4FEF, the sprite registers take the rest of the 2K
+const RAM_SIZE: usize = 2032;
 
 pub struct MemoryMapper<'a> {
-    roms: &'a Box<Roms>,
-    ram: [u8; 2032],
+    roms: &'a Roms,
+    ram: [u8; RAM_SIZE],
     tile_ram: [usize; 0x400],
     palette_ram: [usize; 0x400],
+    // sprite number and flips, then palette, for each of the 8 sprites
+    sprite_ram: [u8; 16],
+    // x then y for each sprite
+    sprite_coords: [u8; 16],
+    // set through 0x5003 on cocktail cabinets to turn the picture around
+    // for player two
+    flip_screen: bool,
+    // bit 0 of 0x5000, which lets the VBLANK interrupt through to the CPU
+    interrupt_enabled: bool,
+    // whether the game wrote to 0x50C0 since the last take_watchdog_kick
+    watchdog_kicked: bool,
+    pub input: Input,
+    pub dip_switches: DipSwitches,
+    pub sound: Wsg,
 }
 
 impl<'a> MemoryMapper<'a> {
-    pub fn new(roms: &'a Box<Roms>) -> Self {
+    pub fn new(roms: &'a Roms) -> Self {
         MemoryMapper {
             roms: roms,
-            ram: [0; 2032],
+            ram: [0; RAM_SIZE],
             tile_ram: [0; 0x400],
             palette_ram: [0; 0x400],
+            sprite_ram: [0; 16],
+            sprite_coords: [0; 16],
+            flip_screen: false,
+            interrupt_enabled: false,
+            watchdog_kicked: false,
+            input: Input::new(),
+            dip_switches: DipSwitches::new(),
+            sound: Wsg::new(&roms.sound_roms[0]),
         }
     }
 
+    /// Clears RAM and the output latches the way a reset of the board does.
+    /// Inputs and DIP switches are left alone
+    pub fn reset(&mut self) {
+        self.ram = [0; RAM_SIZE];
+        self.tile_ram = [0; 0x400];
+        self.palette_ram = [0; 0x400];
+        self.sprite_ram = [0; 16];
+        self.sprite_coords = [0; 16];
+        self.flip_screen = false;
+        self.interrupt_enabled = false;
+        self.watchdog_kicked = false;
+        self.sound.set_enabled(false);
+    }
+
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt_enabled
+    }
+
+    /// Returns whether the watchdog was reset since the last call
+    pub fn take_watchdog_kick(&mut self) -> bool {
+        let kicked = self.watchdog_kicked;
+        self.watchdog_kicked = false;
+        kicked
+    }
+
+    /// Appends RAM, the output latches and the sound registers to <state>.
+    /// Inputs and DIP switches belong to whoever runs the machine, so they
+    /// aren't included
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        // both only ever hold bytes
+        for byte in self.tile_ram.iter().chain(self.palette_ram.iter()) {
+            state.u8(*byte as u8);
+        }
+        state.bytes(&self.sprite_ram);
+        state.bytes(&self.sprite_coords);
+        state.bool(self.flip_screen);
+        state.bool(self.interrupt_enabled);
+        state.bool(self.watchdog_kicked);
+        self.sound.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.ram.copy_from_slice(state.bytes(RAM_SIZE)?);
+        for (tile, byte) in self.tile_ram.iter_mut().zip(state.bytes(0x400)?) {
+            *tile = *byte as usize;
+        }
+        for (palette, byte) in self.palette_ram.iter_mut().zip(state.bytes(0x400)?) {
+            *palette = *byte as usize;
+        }
+        self.sprite_ram.copy_from_slice(state.bytes(16)?);
+        self.sprite_coords.copy_from_slice(state.bytes(16)?);
+        self.flip_screen = state.bool()?;
+        self.interrupt_enabled = state.bool()?;
+        self.watchdog_kicked = state.bool()?;
+        self.sound.load_state(state)
+    }
+
     fn map(addr: u16, writing: bool) -> Address {
         match map_address(addr, writing) {
             Ok(addr) => addr,
@@ -28,13 +115,62 @@ impl<'a> MemoryMapper<'a> {
     }
 
     pub fn render(&self, display: &mut Display) {
-        let mut addr = 0x3A0;
-        for x in 2..30 {
-            for y in 2..34 {
-                display.draw_tile(x - 2, y - 2, self.tile_ram[addr], self.palette_ram[addr]);
-                addr -= 1;
+        for row in 0..36 {
+            for col in 0..28 {
+                let addr = tile_address(col, row);
+                // only the low 5 bits of colour RAM pick the palette
+                let palette = self.palette_ram[addr] & 0x1F;
+                let tile = self.tile_ram[addr];
+                if self.flip_screen {
+                    display.draw_tile_flipped(27 - col, 35 - row, tile, palette, true, true);
+                } else {
+                    display.draw_tile(col, row, tile, palette);
+                }
             }
         }
+
+        self.render_sprites(display);
+    }
+
+    // sprite 0 has the highest priority, so it's drawn last
+    fn render_sprites(&self, display: &mut Display) {
+        for sprite in (0..8).rev() {
+            let attributes = self.sprite_ram[sprite * 2];
+            // as with tiles, the bits above 5 don't reach the palette PROM
+            let palette = (self.sprite_ram[sprite * 2 + 1] & 0x1F) as usize;
+            let mut x_flip = attributes & 0b10 != 0;
+            let mut y_flip = attributes & 0b01 != 0;
+
+            // the registers count from the bottom right of the screen
+            let mut x = 239 - self.sprite_coords[sprite * 2] as i32;
+            let mut y = 272 - self.sprite_coords[sprite * 2 + 1] as i32;
+            // the hardware shows the first three sprites a pixel off from
+            // the rest
+            if sprite < 3 {
+                y -= 1;
+            }
+
+            if self.flip_screen {
+                x = (WIDTH - 16) as i32 - x;
+                y = (HEIGHT - 16) as i32 - y;
+                x_flip = !x_flip;
+                y_flip = !y_flip;
+            }
+
+            display.draw_sprite(x, y, (attributes >> 2) as usize, palette, x_flip, y_flip);
+        }
+    }
+}
+
+// offset into tile/colour RAM of the tile at (<col>, <row>) on the 28x36
+// screen. the playfield is stored a column at a time starting from the right,
+// while the two status rows at the top and the two at the bottom are stored a
+// row at a time, also from the right, with two unused tiles at either end
+fn tile_address(col: usize, row: usize) -> usize {
+    match row {
+        0 | 1 => 0x3C2 + row * 0x20 + (27 - col),
+        34 | 35 => 0x002 + (row - 34) * 0x20 + (27 - col),
+        _ => 0x040 + (27 - col) * 0x20 + (row - 2),
     }
 }
 
@@ -50,6 +186,23 @@ impl<'a> Memory for MemoryMapper<'a> {
             Address::VramPalettes(offset) => {
                 self.palette_ram[offset] = byte as usize;
             }
+            Address::VramSprites(offset) => {
+                self.sprite_ram[offset] = byte;
+            }
+            Address::SpriteXYRegister(offset) => {
+                self.sprite_coords[offset] = byte;
+            }
+            Address::InterruptEnable => {
+                self.interrupt_enabled = byte & 1 != 0;
+            }
+            Address::FlipScreenRegister => {
+                self.flip_screen = byte & 1 != 0;
+            }
+            Address::WatchdogTimerReset => {
+                self.watchdog_kicked = true;
+            }
+            Address::Sound(offset) => self.sound.write(offset, byte),
+            Address::SoundEnable => self.sound.set_enabled(byte & 1 != 0),
             _ => {}
         }
     }
@@ -59,13 +212,20 @@ impl<'a> Memory for MemoryMapper<'a> {
             Address::GameRom(offset) => self.roms.game_roms[offset / 0x1000][offset % 0x1000],
 
             Address::Ram(offset) => self.ram[offset],
+            Address::VramSprites(offset) => self.sprite_ram[offset],
+            Address::IN0Register => self.input.in0(),
+            Address::IN1Register => self.input.in1(),
+            Address::DipSwitchRegister => self.dip_switches.value(),
             _ => 0,
         }
     }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use display::Color;
+    use input::Button;
     use std::boxed::Box;
 
     #[test]
@@ -95,6 +255,126 @@ mod tests {
         assert_eq!(mapper.read_byte(0x4803), 0x1);
     }
 
+    #[test]
+    fn test_tile_address() {
+        // playfield corners
+        assert_eq!(tile_address(0, 2), 0x3A0);
+        assert_eq!(tile_address(27, 2), 0x040);
+        assert_eq!(tile_address(27, 33), 0x05F);
+        assert_eq!(tile_address(0, 33), 0x3BF);
+        // status rows
+        assert_eq!(tile_address(0, 0), 0x3DD);
+        assert_eq!(tile_address(27, 1), 0x3E2);
+        assert_eq!(tile_address(0, 34), 0x01D);
+        assert_eq!(tile_address(27, 35), 0x022);
+    }
+
+    #[test]
+    fn test_sprite_registers() {
+        let roms = Box::new(Roms::new());
+        let mut mapper = MemoryMapper::new(&roms);
+
+        mapper.write_byte(0x2A, 0x4FF2);
+        mapper.write_byte(0x05, 0x4FF3);
+        mapper.write_byte(0x80, 0x5062);
+        assert_eq!(mapper.sprite_ram[2], 0x2A);
+        assert_eq!(mapper.sprite_ram[3], 0x05);
+        assert_eq!(mapper.sprite_coords[2], 0x80);
+        assert_eq!(mapper.read_byte(0x4FF2), 0x2A);
+
+        // palette RAM is not sprite RAM
+        mapper.write_byte(0x07, 0x4402);
+        assert_eq!(mapper.palette_ram[2], 0x07);
+        assert_eq!(mapper.sprite_ram[2], 0x2A);
+    }
+
+    #[test]
+    fn test_sprite_palette() {
+        let mut roms = Box::new(Roms::new());
+        roms.sprite_rom = [1; 16384];
+        roms.color_rom[1] = Color::rgb(255, 0, 0);
+        roms.color_rom[2] = Color::rgb(0, 255, 0);
+        roms.palette_rom[1] = [0, 1, 1, 1];
+        roms.palette_rom[33] = [0, 2, 2, 2];
+        let mut mapper = MemoryMapper::new(&roms);
+        let mut display = Display::new(&roms);
+
+        // sprite 0 at (100, 99) with junk in bit 5 of its palette
+        mapper.write_byte(0x21, 0x4FF1);
+        mapper.write_byte(139, 0x5060);
+        mapper.write_byte(172, 0x5061);
+        mapper.render(&mut display);
+        assert_eq!(display.pixel(104, 103), Color::rgb(255, 0, 0));
+    }
+
+    #[test]
+    fn test_flip_screen() {
+        let roms = Box::new(Roms::new());
+        let mut mapper = MemoryMapper::new(&roms);
+
+        mapper.write_byte(0x01, 0x5003);
+        assert!(mapper.flip_screen);
+        mapper.write_byte(0x00, 0x5003);
+        assert!(!mapper.flip_screen);
+    }
+
+    #[test]
+    fn test_interrupt_enable() {
+        let roms = Box::new(Roms::new());
+        let mut mapper = MemoryMapper::new(&roms);
+
+        assert!(!mapper.interrupt_enabled());
+        mapper.write_byte(0x01, 0x5000);
+        assert!(mapper.interrupt_enabled());
+        mapper.reset();
+        assert!(!mapper.interrupt_enabled());
+    }
+
+    #[test]
+    fn test_watchdog_kick() {
+        let roms = Box::new(Roms::new());
+        let mut mapper = MemoryMapper::new(&roms);
+
+        assert!(!mapper.take_watchdog_kick());
+        mapper.write_byte(0x00, 0x50C0);
+        assert!(mapper.take_watchdog_kick());
+        assert!(!mapper.take_watchdog_kick());
+    }
+
+    #[test]
+    fn test_input_read() {
+        let roms = Box::new(Roms::new());
+        let mut mapper = MemoryMapper::new(&roms);
+
+        assert_eq!(mapper.read_byte(0x5000), 0xFF);
+        mapper.input.press(Button::CoinOne);
+        assert_eq!(mapper.read_byte(0x5000), 0b1101_1111);
+        mapper.input.press(Button::StartOne);
+        assert_eq!(mapper.read_byte(0x507F), 0b1101_1111);
+    }
+
+    #[test]
+    fn test_dip_switch_read() {
+        let roms = Box::new(Roms::new());
+        let mut mapper = MemoryMapper::new(&roms);
+
+        mapper.dip_switches.set("lives", "5").unwrap();
+        assert_eq!(mapper.read_byte(0x5080), 0b1100_1101);
+        assert_eq!(mapper.read_byte(0x50BF), 0b1100_1101);
+    }
+
+    #[test]
+    fn test_sound_write() {
+        let mut roms = Box::new(Roms::new());
+        roms.sound_roms[0] = [0x0F; 256];
+        let mut mapper = MemoryMapper::new(&roms);
+
+        mapper.write_byte(0x01, 0x5001);
+        mapper.write_byte(0x0A, 0x5055);
+        mapper.sound.run(32);
+        assert_eq!(mapper.sound.take_samples(), vec![7 * 10 * 64]);
+    }
+
     #[test]
     #[should_panic]
     fn test_invalid_write() {
@@ -1,115 +1,231 @@
 use cpu::mem::Memory;
 use cpu::Z80;
+use dip_switches::DipSwitches;
 use display::Display;
+use frontend::Frontend;
+use input::{Cabinet, Input};
 use interrupt_vector::InterruptVector;
 use memory_mapper::MemoryMapper;
 use rom::Roms;
+use savestate::{StateReader, StateWriter};
 
-use sdl2;
-use sdl2::event::Event;
 use std::io;
 
+// the video hardware draws 264 lines of 192 CPU cycles each, and blanks
+// from line 224 on. that's 50688 cycles a frame, or 60.606 frames a second
+const CYCLES_PER_SCANLINE: usize = 192;
+const CYCLES_PER_FRAME: usize = CYCLES_PER_SCANLINE * 264;
+const VBLANK_CYCLE: usize = CYCLES_PER_SCANLINE * 224;
+
+// frames the game may go without writing to 0x50C0 before the board resets
+const WATCHDOG_FRAMES: u32 = 16;
+
 pub struct PacmanSystem<'a> {
-    roms: &'a Box<Roms>,
     cpu: Z80,
     memory: MemoryMapper<'a>,
     // just for now
     io: InterruptVector,
     display: Display<'a>,
+    // CPU cycles since the beam was at the top of the screen
+    frame_cycles: usize,
+    watchdog_enabled: bool,
+    // frames since the watchdog was last reset
+    watchdog_frames: u32,
+    // how many times it fired
+    watchdog_resets: u32,
 }
 
 impl<'a> PacmanSystem<'a> {
-    pub fn new(roms: &'a Box<Roms>) -> Self {
+    pub fn new(roms: &'a Roms) -> Self {
         PacmanSystem {
-            roms: roms,
             cpu: Z80::new(),
             memory: MemoryMapper::new(roms),
             io: InterruptVector::new(),
             display: Display::new(roms),
+            frame_cycles: 0,
+            watchdog_enabled: true,
+            watchdog_frames: 0,
+            watchdog_resets: 0,
         }
     }
 
-    pub fn start(&mut self) {
-        /*
-        while true {
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) {
+        self.memory.dip_switches = dip_switches;
+    }
+
+    pub fn set_cabinet(&mut self, cabinet: Cabinet) {
+        self.memory.input.set_cabinet(cabinet);
+    }
+
+    /// Disabling the watchdog lets a hung program keep running instead of
+    /// being reset, which is handy when debugging it
+    pub fn set_watchdog(&mut self, enabled: bool) {
+        self.watchdog_enabled = enabled;
+    }
+
+    /// Resets the CPU and clears RAM, like the reset line on the board
+    pub fn reset(&mut self) {
+        self.cpu = Z80::new();
+        self.memory.reset();
+        self.watchdog_frames = 0;
+        self.frame_cycles = 0;
+    }
+
+    /// The last frame drawn by run_frame
+    pub fn display(&self) -> &Display<'a> {
+        &self.display
+    }
+
+    /// The IN0 and IN1 switches, for frontends to feed input into
+    pub fn input(&mut self) -> &mut Input {
+        &mut self.memory.input
+    }
+
+    /// Sound generated since the last call, at the WSG's 96 kHz
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.memory.sound.take_samples()
+    }
+
+    /// How many times the watchdog has had to reset the game
+    pub fn watchdog_resets(&self) -> u32 {
+        self.watchdog_resets
+    }
+
+    /// Runs the CPU from one VBLANK to the next and draws the frame
+    pub fn run_frame(&mut self) {
+        while !self.run_slice() {}
+        self.memory.render(&mut self.display);
+        self.tick_watchdog();
+    }
+
+    /// Runs frames through <frontend> until it asks to quit
+    pub fn run<F: Frontend>(&mut self, frontend: &mut F) -> Result<(), String> {
+        while self.run_once(frontend)? {}
+        Ok(())
+    }
+
+    /// Polls <frontend> for input and plays one frame through it, for hosts
+    /// that call in once a frame rather than handing over the main loop.
+    /// Returns false, without running anything, if the frontend asked to
+    /// quit
+    pub fn run_once<F: Frontend>(&mut self, frontend: &mut F) -> Result<bool, String> {
+        frontend.poll_input(&mut self.memory.input);
+        if frontend.should_quit() {
+            return Ok(false);
         }
-        */
 
-        let sdl_context = sdl2::init().unwrap();
-        let video = sdl_context.video().unwrap();
-        let window = video
-            .window("Pacman", 488, 576)
-            .position_centered()
-            .build()
-            .unwrap();
+        self.run_frame();
+        frontend.present(&self.display)?;
+        let samples = self.take_samples();
+        frontend.queue_audio(&samples);
+        frontend.end_frame();
+        Ok(true)
+    }
 
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        let mut canvas = window.into_canvas().build().unwrap();
+    /// Snapshot of the CPU, RAM and the rest of the board, which is always
+    /// the same size for a given version of the emulator. The picture isn't
+    /// included, it's drawn again by the next frame
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.cpu.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        state.u8(self.io.data);
+        state.u32(self.frame_cycles as u32);
+        state.u32(self.watchdog_frames);
+        state.finish()
+    }
 
-        let mut cycles = 0;
-        'main: loop {
-            cycles += self.cpu.run_opcodes(5, &mut self.memory, &mut self.io);
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. } => break 'main,
-                    _ => {}
-                }
-            }
+    /// Restores a snapshot made by save_state. If <data> isn't a valid one
+    /// the machine may be left half restored, and should be reset
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data)?;
+        self.cpu.load_state(&mut state)?;
+        self.memory.load_state(&mut state)?;
+        self.io.data = state.u8()?;
+        self.frame_cycles = state.u32()? as usize % CYCLES_PER_FRAME;
+        self.watchdog_frames = state.u32()?;
+        state.finish()
+    }
 
-            self.memory.render(&mut self.display);
-            canvas.present();
+    // runs a few instructions, raising the VBLANK interrupt when the beam
+    // reaches line 224 if the game has enabled it. returns true when VBLANK
+    // starts, which is when the frame is finished
+    fn run_slice(&mut self) -> bool {
+        let elapsed = self.cpu.run_opcodes(5, &mut self.memory, &mut self.io);
+        self.memory.sound.run(elapsed);
 
-            //println!("{}", self.io.data);
-            if cycles > 51200 {
-                cycles = 0;
-                self.memory.render(&mut self.display);
-                self.display.show(&mut canvas);
-                canvas.present();
-                self.cpu.interrupt(self.io.data);
-            }
+        // turning the latch off also drops a request the CPU hasn't taken
+        if !self.memory.interrupt_enabled() {
+            self.cpu.clear_interrupt();
         }
+
+        let vblank =
+            self.frame_cycles < VBLANK_CYCLE && self.frame_cycles + elapsed >= VBLANK_CYCLE;
+        if vblank && self.memory.interrupt_enabled() {
+            // the vector is whatever the game last wrote to port 0
+            self.cpu.interrupt(self.io.data);
+        }
+
+        self.frame_cycles += elapsed;
+        if self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frame_cycles -= CYCLES_PER_FRAME;
+        }
+        vblank
     }
 
-    pub fn debug(&mut self) {
+    // called once a frame, resets the system if the game stopped resetting
+    // the watchdog
+    fn tick_watchdog(&mut self) {
+        if self.memory.take_watchdog_kick() {
+            self.watchdog_frames = 0;
+            return;
+        }
+
+        self.watchdog_frames += 1;
+        if self.watchdog_enabled && self.watchdog_frames >= WATCHDOG_FRAMES {
+            eprintln!(
+                "Watchdog fired: no reset in {} frames (pc = 0x{:04x}), resetting",
+                WATCHDOG_FRAMES,
+                self.cpu.get_pc()
+            );
+            self.reset();
+            self.watchdog_resets += 1;
+        }
+    }
+
+    /// Runs the machine one instruction at a time under a simple debugger
+    /// reading commands from stdin: "b <address>" to break at an address
+    /// and "s" to step. Returns when stdin is closed
+    pub fn debug(&mut self) -> Result<(), String> {
         let mut input = String::new();
         // break at the beginning
         let mut break_point: u16 = 0;
         let mut step = false;
 
-        while true {
-            input.clear();
+        loop {
             let pc = self.cpu.get_pc();
 
             if step || pc == break_point {
-                let result = io::stdin().read_line(&mut input);
-                match result {
-                    Ok(n) => {
-                        println!("{}", n);
-                    }
-                    Err(error) => {
-                        println!("{}", error);
-                    }
+                input.clear();
+                let read = io::stdin()
+                    .read_line(&mut input)
+                    .map_err(|why| format!("Couldn't read a command: {}", why))?;
+                if read == 0 {
+                    return Ok(());
                 }
-                let split: Vec<&str> = input.split(" ").collect();
-                match split.get(0) {
-                    Some(result) => {
-                        println!("{}", *result);
-                        match *result {
-                            "b" | "break" => {
-                                break_point = match split.get(1) {
-                                    Some(addr) => addr.parse().unwrap(),
-                                    None => 0,
-                                }
-                            }
-                            "s" | "step" => {
-                                println!("asdf");
-                                step = true;
-                            }
-                            &_ => {
-                                println!("Invalid command");
-                            }
+
+                let mut words = input.split_whitespace();
+                match words.next() {
+                    Some("b") | Some("break") => {
+                        break_point = match words.next() {
+                            Some(addr) => addr
+                                .parse()
+                                .map_err(|_| format!("Invalid break point: {}", addr))?,
+                            None => 0,
                         }
                     }
+                    Some("s") | Some("step") => step = true,
+                    Some(_) => println!("Invalid command"),
                     None => {}
                 }
                 println!("opcode: {:x}", self.memory.read_byte(pc));
@@ -120,3 +236,132 @@ impl<'a> PacmanSystem<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Button;
+
+    // holds a coin in for a few frames and keeps what it was given
+    struct Recorder {
+        frames: usize,
+        samples: usize,
+        in0: Vec<u8>,
+    }
+
+    impl Frontend for Recorder {
+        fn poll_input(&mut self, input: &mut Input) {
+            input.set(Button::CoinOne, self.frames < 2);
+            self.in0.push(input.in0());
+        }
+
+        fn should_quit(&self) -> bool {
+            self.frames == 3
+        }
+
+        fn present(&mut self, _: &Display) -> Result<(), String> {
+            self.frames += 1;
+            Ok(())
+        }
+
+        fn queue_audio(&mut self, samples: &[i16]) {
+            self.samples += samples.len();
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let roms = Box::new(Roms::new());
+        let mut pacman = PacmanSystem::new(&roms);
+        let mut recorder = Recorder {
+            frames: 0,
+            samples: 0,
+            in0: Vec::new(),
+        };
+
+        assert_eq!(pacman.run(&mut recorder), Ok(()));
+        assert_eq!(recorder.frames, 3);
+        assert_eq!(recorder.in0, vec![0xDF, 0xDF, 0xFF, 0xFF]);
+        // the sound is off, but the WSG still produces silence at 96 kHz
+        assert!(recorder.samples > 3 * 1500);
+    }
+
+    #[test]
+    fn test_vblank_timing() {
+        // a ROM full of NOPs
+        let roms = Box::new(Roms::new());
+        let mut pacman = PacmanSystem::new(&roms);
+
+        // 5 NOPs are 20 cycles
+        while !pacman.run_slice() {}
+        assert!(pacman.frame_cycles >= VBLANK_CYCLE);
+        assert!(pacman.frame_cycles < VBLANK_CYCLE + 20);
+
+        let mut cycles = 20;
+        while !pacman.run_slice() {
+            cycles += 20;
+        }
+        assert!(cycles + 20 > CYCLES_PER_FRAME && cycles < CYCLES_PER_FRAME + 20);
+    }
+
+    #[test]
+    fn test_save_state() {
+        // a loop incrementing (0x4C00) forever, with the sound on
+        let mut roms = Box::new(Roms::new());
+        roms.game_roms[0][..11].copy_from_slice(&[
+            0x3E, 0x01, 0x32, 0x01, 0x50, 0x21, 0x00, 0x4C, 0x34, 0x18, 0xFD,
+        ]);
+        let mut pacman = PacmanSystem::new(&roms);
+        pacman.set_watchdog(false);
+        pacman.memory.write_byte(0x0F, 0x5055);
+
+        pacman.run_frame();
+        pacman.take_samples();
+        let state = pacman.save_state();
+        pacman.run_frame();
+        let after = (pacman.save_state(), pacman.take_samples());
+
+        // it comes back to the same place even in a different machine
+        let mut other = PacmanSystem::new(&roms);
+        assert_eq!(other.load_state(&state), Ok(()));
+        assert_eq!(other.save_state(), state);
+        other.run_frame();
+        assert_eq!((other.save_state(), other.take_samples()), after);
+
+        assert!(other.load_state(&state[..state.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let roms = Box::new(Roms::new());
+        let mut pacman = PacmanSystem::new(&roms);
+
+        pacman.memory.write_byte(0x12, 0x4800);
+        for _ in 0..WATCHDOG_FRAMES - 1 {
+            pacman.tick_watchdog();
+        }
+        // kicking it starts the count again
+        pacman.memory.write_byte(0x00, 0x50C0);
+        pacman.tick_watchdog();
+        for _ in 0..WATCHDOG_FRAMES - 1 {
+            pacman.tick_watchdog();
+        }
+        assert_eq!(pacman.memory.read_byte(0x4800), 0x12);
+
+        pacman.tick_watchdog();
+        assert_eq!(pacman.memory.read_byte(0x4800), 0x00);
+    }
+
+    #[test]
+    fn test_watchdog_disabled() {
+        let roms = Box::new(Roms::new());
+        let mut pacman = PacmanSystem::new(&roms);
+        pacman.set_watchdog(false);
+
+        pacman.memory.write_byte(0x12, 0x4800);
+        for _ in 0..WATCHDOG_FRAMES * 2 {
+            pacman.tick_watchdog();
+        }
+        assert_eq!(pacman.memory.read_byte(0x4800), 0x12);
+    }
+}
@@ -1,13 +1,11 @@
-use sdl2::pixels::Color;
+use checksum::{crc32, sha1};
+use display::Color;
+use rom_database::{self, Dump, Region, RomSet, REGIONS, SETS};
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-
-const GAME_ROM_FILE_NAMES: [&str; 4] = ["pacman.6e", "pacman.6f", "pacman.6h", "pacman.6j"];
-const COLOR_ROM_FILE_NAME: &str = "82s123.7f";
-const PALETTE_ROM_FILE_NAME: &str = "82s126.4a";
-const TILE_ROM_FILE_NAME: &str = "pacman.5e";
-const SPRITE_ROM_FILE_NAME: &str = "pacman.5f";
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
 
 pub struct Roms {
     pub game_roms: [[u8; 4096]; 4],
@@ -23,12 +21,7 @@ impl Roms {
     pub fn new() -> Self {
         Roms {
             game_roms: [[0; 4096]; 4],
-            color_rom: [Color {
-                r: 0,
-                g: 0,
-                b: 0,
-                a: 0,
-            }; 32],
+            color_rom: [Color::rgb(0, 0, 0); 32],
             palette_rom: [[0; 4]; 64],
             tile_rom: [0; 16384],
             sprite_rom: [0; 16384],
@@ -36,36 +29,50 @@ impl Roms {
         }
     }
 
-    pub fn load(directory: &Path) -> Self {
+    /// Loads <set> from <path>, a directory or a MAME style zip archive.
+    /// Files missing from either are looked for in the archives of the set
+    /// and the sets it's a clone of, inside the directory or next to the
+    /// archive.
+    ///
+    /// If any of them is missing, the wrong size or can't be read, the error
+    /// is a report on every file, good or bad
+    pub fn load(path: &Path, set: &RomSet) -> Result<Self, RomReport> {
+        let mut loader = Loader {
+            sources: sources(path, set),
+            report: RomReport { files: Vec::new() },
+        };
+        // the chips of each region, wherever the set puts them
+        let mut regions: Vec<Vec<u8>> = REGIONS
+            .iter()
+            .map(|region| vec![0; region.size()])
+            .collect();
+        for dump in set.dumps {
+            let bytes = loader.read(dump);
+            regions[dump.region as usize][dump.offset..dump.offset + dump.size]
+                .copy_from_slice(&bytes);
+        }
+
         let mut roms = Roms::new();
+        let program = &regions[Region::Program as usize];
+        for (game_rom, bytes) in roms.game_roms.iter_mut().zip(program.chunks(0x1000)) {
+            game_rom.copy_from_slice(bytes);
+        }
+        roms.load_color_rom(&regions[Region::Colors as usize]);
+        roms.load_palette_rom(&regions[Region::Palettes as usize]);
+        roms.tile_rom = decode_graphics(&regions[Region::Tiles as usize]);
+        roms.sprite_rom = decode_graphics(&regions[Region::Sprites as usize]);
+        let sound = &regions[Region::Sound as usize];
+        roms.sound_roms[0].copy_from_slice(&sound[..0x100]);
+        roms.sound_roms[1].copy_from_slice(&sound[0x100..]);
 
-        roms.load_game_roms(directory);
-        roms.load_color_rom(directory);
-        roms.load_palette_rom(directory);
-        roms.load_tile_rom(directory);
-        roms.load_sprite_rom(directory);
-        roms
-    }
-
-    fn load_game_roms(&mut self, directory: &Path) {
-        for file_name in GAME_ROM_FILE_NAMES.iter() {
-            let path = directory.join(file_name);
-            let mut buf: &mut [u8];
-            buf = match *file_name {
-                "pacman.6e" => &mut self.game_roms[0],
-                "pacman.6f" => &mut self.game_roms[1],
-                "pacman.6h" => &mut self.game_roms[2],
-                "pacman.6j" => &mut self.game_roms[3],
-                _ => panic!("Internal error"),
-            };
-            Roms::load_file(&path, &mut buf);
+        if loader.report.is_ok() {
+            Ok(roms)
+        } else {
+            Err(loader.report)
         }
     }
 
-    fn load_color_rom(&mut self, directory: &Path) {
-        let mut bytes: [u8; 32] = [0; 32];
-        Roms::load_file(&directory.join(COLOR_ROM_FILE_NAME), &mut bytes);
-
+    fn load_color_rom(&mut self, bytes: &[u8]) {
         for (i, byte) in bytes.iter().enumerate() {
             let mut color = &mut self.color_rom[i];
             if (byte & 1) != 0 {
@@ -95,51 +102,556 @@ impl Roms {
         }
     }
 
-    fn load_palette_rom(&mut self, directory: &Path) {
-        let mut bytes: [u8; 256] = [0; 256];
-        Roms::load_file(&directory.join(PALETTE_ROM_FILE_NAME), &mut bytes);
-
+    fn load_palette_rom(&mut self, bytes: &[u8]) {
         for (i, byte) in bytes.iter().enumerate() {
             self.palette_rom[i / 4][i % 4] = *byte as usize;
-            let color = self.color_rom[*byte as usize];
         }
     }
+}
 
-    // decodes the bit planes
-    fn load_tile_rom(&mut self, directory: &Path) {
-        let mut bytes: [u8; 4096] = [0; 4096];
-        Roms::load_file(&directory.join(TILE_ROM_FILE_NAME), &mut bytes);
+// splits the bit planes of a tile or sprite ROM into a byte per pixel
+fn decode_graphics(bytes: &[u8]) -> [u8; 16384] {
+    let mut pixels = [0; 16384];
+    for (i, byte) in bytes.iter().enumerate() {
+        for bit in 0..4 {
+            let lsb = (byte & 1 << bit) >> bit;
+            let msb = (byte & 1 << (bit + 4)) >> (bit + 3);
+            pixels[i * 4 + bit] = lsb | msb;
+        }
+    }
+    pixels
+}
 
-        for (i, byte) in bytes.iter().enumerate() {
-            for bit in 0..4 {
-                let lsb = (byte & 1 << bit) >> bit;
-                let msb = (byte & 1 << (bit + 4)) >> (bit + 3);
-                self.tile_rom[i * 4 + bit] = lsb | msb;
+/// What's wrong with a ROM file
+#[derive(Debug)]
+pub enum RomError {
+    Missing,
+    WrongSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// The contents don't match the dump the file should hold
+    BadChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    /// The CRC32 matches the dump but the SHA1 doesn't
+    BadSha1 {
+        expected: &'static str,
+        actual: String,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Missing => write!(f, "missing"),
+            RomError::WrongSize { expected, actual } => {
+                write!(f, "wrong size, {} bytes instead of {}", actual, expected)
             }
+            RomError::BadChecksum { expected, actual } => write!(
+                f,
+                "bad checksum, CRC32 {:08x} instead of {:08x}",
+                actual, expected
+            ),
+            RomError::BadSha1 {
+                expected,
+                ref actual,
+            } => write!(f, "bad checksum, SHA1 {} instead of {}", actual, expected),
+            RomError::Io(ref why) => write!(f, "{}", why),
         }
     }
+}
 
-    fn load_sprite_rom(&mut self, directory: &Path) {
-        let mut bytes: [u8; 4096] = [0; 4096];
-        Roms::load_file(&directory.join(SPRITE_ROM_FILE_NAME), &mut bytes);
+/// Every file of a ROM set, in the order they're loaded, and what was found
+pub struct RomReport {
+    pub files: Vec<(&'static str, Result<(), RomError>)>,
+}
 
-        for (i, byte) in bytes.iter().enumerate() {
-            for bit in 0..4 {
-                let lsb = (byte & 1 << bit) >> bit;
-                let msb = (byte & 1 << (bit + 4)) >> (bit + 3);
-                self.sprite_rom[i * 4 + bit] = lsb | msb;
+impl RomReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|&(_, ref result)| result.is_ok())
+    }
+}
+
+impl fmt::Display for RomReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(file_name, ref result) in self.files.iter() {
+            match *result {
+                Ok(()) => writeln!(f, "  {}: ok", file_name)?,
+                Err(ref why) => writeln!(f, "  {}: {}", file_name, why)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a file compares with the known good dump it should hold
+#[derive(Debug)]
+pub enum Verification {
+    Good,
+    /// It matches the CRC32 of the dump, but there's no SHA1 on record to
+    /// be sure
+    Unknown,
+    Bad(RomError),
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Verification::Good => write!(f, "good"),
+            Verification::Unknown => write!(f, "unknown, only the CRC32 of a good dump is known"),
+            Verification::Bad(ref why) => write!(f, "bad ({})", why),
+        }
+    }
+}
+
+/// Checks every file of <set> at <path>, looked for the way Roms::load
+/// does, against the CRC32 and SHA1 of the known good dumps
+pub fn verify(path: &Path, set: &RomSet) -> Vec<(&'static str, Verification)> {
+    let sources = sources(path, set);
+
+    set.dumps
+        .iter()
+        .map(|dump| {
+            let bytes = match find_file(&sources, dump) {
+                Ok(bytes) => bytes,
+                Err(why) => return (dump.file_name, Verification::Bad(why)),
+            };
+
+            let crc = crc32(&bytes);
+            if crc != dump.crc32 {
+                let why = RomError::BadChecksum {
+                    expected: dump.crc32,
+                    actual: crc,
+                };
+                return (dump.file_name, Verification::Bad(why));
+            }
+            let verification = match dump.sha1 {
+                Some(expected) => {
+                    let actual = sha1(&bytes);
+                    if actual == expected {
+                        Verification::Good
+                    } else {
+                        Verification::Bad(RomError::BadSha1 {
+                            expected: expected,
+                            actual: actual,
+                        })
+                    }
+                }
+                None => Verification::Unknown,
+            };
+            (dump.file_name, verification)
+        })
+        .collect()
+}
+
+/// The known set at <path>: the one a zip archive is named after, or else
+/// the one with the most files matching their dumps' CRC32, then the one
+/// with the most files found. Midway's Pac-Man if nothing is found at all
+pub fn detect_set(path: &Path) -> &'static RomSet {
+    if !path.is_dir() {
+        if let Some(set) = rom_database::find_set(&zip_set_name(path)) {
+            return set;
+        }
+    }
+
+    let mut best = &SETS[0];
+    let mut best_score = (0, 0);
+    for set in SETS {
+        let sources = sources(path, set);
+        let mut score = (0, 0);
+        for dump in set.dumps {
+            if let Ok(bytes) = find_file(&sources, dump) {
+                if crc32(&bytes) == dump.crc32 {
+                    score.0 += 1;
+                }
+                score.1 += 1;
+            }
+        }
+        if score > best_score {
+            best = set;
+            best_score = score;
+        }
+    }
+    best
+}
+
+// reads the files of a set, keeping a report as it goes
+struct Loader {
+    sources: Vec<Source>,
+    report: RomReport,
+}
+
+impl Loader {
+    // the contents of <dump>'s file, or zeroes if it can't be used so that
+    // the rest of the set can still be checked
+    fn read(&mut self, dump: &'static Dump) -> Vec<u8> {
+        match find_file(&self.sources, dump) {
+            Ok(bytes) => {
+                self.report.files.push((dump.file_name, Ok(())));
+                bytes
+            }
+            Err(why) => {
+                self.report.files.push((dump.file_name, Err(why)));
+                vec![0; dump.size]
+            }
+        }
+    }
+}
+
+// somewhere the files of a set are looked for
+enum Source {
+    Directory(PathBuf),
+    Zip(ZipArchive),
+    // an archive that couldn't be opened, anything looked for in it fails
+    // with this
+    Broken(String),
+}
+
+impl Source {
+    fn open_zip(path: &Path) -> Self {
+        match ZipArchive::open(path) {
+            Ok(zip) => Source::Zip(zip),
+            Err(why) => Source::Broken(format!("{}: {}", path.display(), why)),
+        }
+    }
+
+    // the contents of <dump>'s file, or None if it isn't here. archives are
+    // also searched for the dump's CRC-32, so renamed files are found too
+    fn read(&self, dump: &Dump) -> Result<Option<Vec<u8>>, RomError> {
+        match *self {
+            Source::Directory(ref directory) => {
+                let mut file = match File::open(directory.join(dump.file_name)) {
+                    Ok(file) => file,
+                    Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(why) => return Err(RomError::Io(why)),
+                };
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).map_err(RomError::Io)?;
+                Ok(Some(bytes))
             }
+            Source::Zip(ref zip) => {
+                let entry = zip
+                    .find(dump.file_name)
+                    .or_else(|| zip.find_crc(dump.crc32));
+                match entry {
+                    Some(entry) => zip.read(entry).map(Some).map_err(RomError::Io),
+                    None => Ok(None),
+                }
+            }
+            Source::Broken(ref why) => Err(RomError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                why.clone(),
+            ))),
         }
     }
+}
+
+// where to look for the files of <set> at <path>, in order
+fn sources(path: &Path, set: &RomSet) -> Vec<Source> {
+    let (mut sources, directory) = if path.is_dir() {
+        (vec![Source::Directory(path.to_path_buf())], path)
+    } else {
+        (
+            vec![Source::open_zip(path)],
+            path.parent().unwrap_or(Path::new(".")),
+        )
+    };
+    let mut set = Some(set.name);
+
+    // the archives of the set and the sets it's a clone of
+    while let Some(name) = set {
+        let archive = directory.join(format!("{}.zip", name));
+        if archive.is_file() && archive != path {
+            sources.push(Source::open_zip(&archive));
+        }
+        set = rom_database::find_set(name).and_then(|set| set.parent);
+    }
+    sources
+}
+
+// pacman for .../pacman.zip
+fn zip_set_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// the first copy of <dump>'s file in <sources>, which has to be the dump's
+// size
+fn find_file(sources: &[Source], dump: &Dump) -> Result<Vec<u8>, RomError> {
+    let mut found = None;
+    for source in sources {
+        found = source.read(dump)?;
+        if found.is_some() {
+            break;
+        }
+    }
+    let bytes = found.ok_or(RomError::Missing)?;
+
+    if bytes.len() != dump.size {
+        return Err(RomError::WrongSize {
+            expected: dump.size,
+            actual: bytes.len(),
+        });
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use zip::tests::write_zip;
+
+    fn pacman() -> &'static RomSet {
+        rom_database::find_set("pacman").unwrap()
+    }
+
+    // a directory holding <set>'s files at the right sizes, each filled with
+    // its index in the set
+    fn write_set(name: &str, set: &RomSet) -> PathBuf {
+        let directory = env::temp_dir().join(format!("pacman-rom-test-{}", name));
+        fs::create_dir_all(&directory).unwrap();
+        for (i, dump) in set.dumps.iter().enumerate() {
+            fs::write(directory.join(dump.file_name), vec![i as u8; dump.size]).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn test_load() {
+        let directory = write_set("load", pacman());
+        let roms = Roms::load(&directory, pacman()).ok().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+
+        assert_eq!(roms.game_roms[3][0xFFF], 3);
+        // 4 is 0b100, the biggest red resistor
+        assert_eq!(roms.color_rom[31], Color::rgb(0x97, 0, 0));
+        assert_eq!(roms.palette_rom[63], [5; 4]);
+        // 6 is 0b0000_0110, low bits of the middle two pixels
+        assert_eq!(roms.tile_rom[..4], [0, 1, 1, 0]);
+        assert_eq!(roms.sound_roms[1][0], 9);
+    }
+
+    #[test]
+    fn test_load_puckman() {
+        let puckman = rom_database::find_set("puckman").unwrap();
+        let directory = write_set("puckman", puckman);
+        let roms = Roms::load(&directory, puckman).ok().unwrap();
+        fs::remove_dir_all(directory).unwrap();
 
-    fn load_file(path: &Path, buffer: &mut [u8]) {
-        let mut file = match File::open(&path) {
-            Err(why) => panic!("Missing ROMs"),
-            Ok(file) => file,
+        // the program chips alternate between the two rows of the board
+        assert_eq!(roms.game_roms[0][..0x800], [0; 0x800][..]);
+        assert_eq!(roms.game_roms[0][0x800], 1);
+        assert_eq!(roms.game_roms[3][0xFFF], 7);
+        // then chg1 and chg2 are the tiles, chg3 and chg4 the sprites
+        assert_eq!(roms.tile_rom[0x800 * 4], decode_graphics(&[11])[0]);
+        assert_eq!(roms.sprite_rom[0], decode_graphics(&[12])[0]);
+        assert_eq!(roms.sound_roms, [[14; 256], [15; 256]]);
+    }
+
+    #[test]
+    fn test_detect_set() {
+        let directory = write_set("detect", rom_database::find_set("puckman").unwrap());
+        assert_eq!(detect_set(&directory).name, "puckman");
+        // a directory with the files of several sets is the one most of
+        // them match the hashes of
+        let hangly = rom_database::find_set("hangly").unwrap();
+        for dump in hangly.dumps {
+            fs::write(directory.join(dump.file_name), vec![0; dump.size]).unwrap();
+        }
+        fs::write(directory.join("82s123.7f"), good_color_rom()).unwrap();
+        assert_eq!(detect_set(&directory).name, "hangly");
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(detect_set(&directory.join("pacmod.zip")).name, "pacmod");
+        assert_eq!(detect_set(&directory).name, "pacman");
+    }
+
+    #[test]
+    fn test_report() {
+        let directory = write_set("report", pacman());
+        fs::remove_file(directory.join("pacman.6f")).unwrap();
+        fs::write(directory.join("82s123.7f"), [0; 31]).unwrap();
+        let report = Roms::load(&directory, pacman()).err().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+
+        assert!(!report.is_ok());
+        assert_eq!(report.files.len(), 10);
+        assert!(matches!(report.files[0], ("pacman.6e", Ok(()))));
+        assert!(matches!(
+            report.files[1],
+            ("pacman.6f", Err(RomError::Missing))
+        ));
+        assert!(matches!(
+            report.files[4],
+            (
+                "82s123.7f",
+                Err(RomError::WrongSize {
+                    expected: 32,
+                    actual: 31
+                })
+            )
+        ));
+        assert!(report
+            .to_string()
+            .contains("  82s123.7f: wrong size, 31 bytes instead of 32\n"));
+    }
+
+    // the color PROM is small enough to make a good one of
+    fn good_color_rom() -> Vec<u8> {
+        let mut bytes = vec![
+            0x00, 0x07, 0x66, 0xEF, 0x00, 0xF8, 0xEA, 0x6F, 0x00, 0x3F, 0x00, 0xC9, 0x38, 0xAA,
+            0xAF, 0xF6,
+        ];
+        bytes.resize(32, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_zip() {
+        let directory = write_set("zip", pacman());
+        let file = |file_name: &str| fs::read(directory.join(file_name)).unwrap();
+        let mut files: Vec<(&str, Vec<u8>)> = pacman().dumps[..4]
+            .iter()
+            .map(|dump| (dump.file_name, file(dump.file_name)))
+            .collect();
+        for file_name in ["82s126.4a", "pacman.5e", "pacman.5f"].iter() {
+            files.push((file_name, file(file_name)));
+        }
+        files.push(("82S126.3M", file("82s126.3m")));
+        let files: Vec<(&str, &[u8])> = files
+            .iter()
+            .map(|&(name, ref bytes)| (name, &bytes[..]))
+            .collect();
+        fs::write(directory.join("pacman.zip"), write_zip(&files)).unwrap();
+        // Puck Man's board has the same color PROM under another name
+        let color_rom = good_color_rom();
+        fs::write(
+            directory.join("puckman.zip"),
+            write_zip(&[("pm1-1.7f", &color_rom)]),
+        )
+        .unwrap();
+        for dump in pacman().dumps {
+            if dump.file_name != "82s126.1m" {
+                fs::remove_file(directory.join(dump.file_name)).unwrap();
+            }
+        }
+
+        let roms = Roms::load(&directory, pacman()).ok().unwrap();
+        assert_eq!(roms.game_roms[3][0], 3);
+        assert_eq!(roms.color_rom[1], Color::rgb(0xFF, 0, 0));
+        assert_eq!(roms.sound_roms, [[8; 256], [9; 256]]);
+
+        // the loose file isn't looked for when loading the archive
+        let report = Roms::load(&directory.join("pacman.zip"), pacman())
+            .err()
+            .unwrap();
+        fs::remove_dir_all(directory).unwrap();
+        let problems: Vec<&str> = report
+            .files
+            .iter()
+            .filter(|&&(_, ref result)| result.is_err())
+            .map(|&(file_name, _)| file_name)
+            .collect();
+        assert_eq!(problems, vec!["82s126.1m"]);
+    }
+
+    #[test]
+    fn test_broken_zip() {
+        let directory = write_set("broken-zip", pacman());
+        fs::remove_file(directory.join("pacman.6e")).unwrap();
+        fs::write(directory.join("pacman.zip"), b"not a zip").unwrap();
+        let report = Roms::load(&directory, pacman()).err().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+
+        assert!(matches!(
+            report.files[0],
+            ("pacman.6e", Err(RomError::Io(_)))
+        ));
+        assert!(report.files[1].1.is_ok());
+    }
+
+    #[test]
+    fn test_verify() {
+        let directory = write_set("verify", pacman());
+        fs::write(directory.join("82s123.7f"), good_color_rom()).unwrap();
+        fs::remove_file(directory.join("pacman.6h")).unwrap();
+        let verified = verify(&directory, pacman());
+        fs::remove_dir_all(directory).unwrap();
+
+        assert_eq!(verified.len(), 10);
+        assert!(matches!(
+            verified[1],
+            (
+                "pacman.6f",
+                Verification::Bad(RomError::BadChecksum {
+                    expected: 0x1A6F_B2D4,
+                    ..
+                })
+            )
+        ));
+        assert!(matches!(
+            verified[2],
+            ("pacman.6h", Verification::Bad(RomError::Missing))
+        ));
+        assert!(matches!(verified[4], ("82s123.7f", Verification::Good)));
+    }
+
+    #[test]
+    fn test_verify_without_sha1() {
+        static SET: RomSet = RomSet {
+            name: "test",
+            description: "Only a CRC32 on record",
+            parent: None,
+            dumps: &[Dump {
+                file_name: "82s123.7f",
+                size: 32,
+                crc32: 0x190A_55AD,
+                sha1: None,
+                region: Region::Colors,
+                offset: 0,
+            }],
         };
-        match file.read(buffer) {
-            Ok(_) => {}
-            Err(why) => panic!("Error reading ROM"),
+        let directory = write_set("verify-without-sha1", &SET);
+        let verified = verify(&directory, &SET);
+        fs::remove_dir_all(directory).unwrap();
+
+        assert!(matches!(verified[0], ("82s123.7f", Verification::Unknown)));
+    }
+
+    #[test]
+    fn test_verify_bad_sha1() {
+        static SET: RomSet = RomSet {
+            name: "test",
+            description: "A CRC32 collision",
+            parent: None,
+            dumps: &[Dump {
+                file_name: "82s123.7f",
+                size: 32,
+                crc32: 0x190A_55AD,
+                sha1: Some("0000000000000000000000000000000000000000"),
+                region: Region::Colors,
+                offset: 0,
+            }],
         };
+        let directory = write_set("verify-bad-sha1", &SET);
+        let verified = verify(&directory, &SET);
+        fs::remove_dir_all(directory).unwrap();
+
+        assert!(matches!(
+            verified[0],
+            ("82s123.7f", Verification::Bad(RomError::BadSha1 { .. }))
+        ));
+        assert_eq!(
+            verified[0].1.to_string(),
+            "bad (bad checksum, SHA1 de8a847bff8c343d69b853a215e6ee775ef2ef96 \
+             instead of 0000000000000000000000000000000000000000)"
+        );
     }
 }
@@ -0,0 +1,525 @@
+// the ROM sets the emulator knows: which chips each is made of, where
+// they're loaded and the hashes of good dumps, from MAME's pacman driver
+
+/// The parts of the machine chips are loaded into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Program,
+    Colors,
+    Palettes,
+    Tiles,
+    Sprites,
+    /// The waveforms, then the timing PROM the WSG is clocked from
+    Sound,
+}
+
+/// Every region, in the order they're declared in
+pub const REGIONS: [Region; 6] = [
+    Region::Program,
+    Region::Colors,
+    Region::Palettes,
+    Region::Tiles,
+    Region::Sprites,
+    Region::Sound,
+];
+
+impl Region {
+    /// How many bytes of chips it takes, whatever set they're from
+    pub fn size(self) -> usize {
+        match self {
+            Region::Program => 0x4000,
+            Region::Colors => 0x20,
+            Region::Palettes => 0x100,
+            // each holds 256 8x8 tiles or 64 16x16 sprites at 2 bits a pixel
+            Region::Tiles | Region::Sprites => 0x1000,
+            Region::Sound => 0x200,
+        }
+    }
+}
+
+/// A known good dump of one chip, and where it's loaded
+pub struct Dump {
+    pub file_name: &'static str,
+    pub size: usize,
+    pub crc32: u32,
+    /// Lowercase hex, as returned by checksum::sha1. MAME lists one for
+    /// every chip here, but those of the chips only the clones have
+    /// (pacfast.6f, hangly.6e-6j, namcopac.6e-6j and pacmanh.*) haven't
+    /// been copied over yet and are None, so those verify as unknown
+    pub sha1: Option<&'static str>,
+    pub region: Region,
+    pub offset: usize,
+}
+
+/// The dumps making up a ROM set, in the order they're loaded
+pub struct RomSet {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The set this one is a clone of. MAME's archives of a clone only hold
+    /// the files that differ from the parent's
+    pub parent: Option<&'static str>,
+    pub dumps: &'static [Dump],
+}
+
+// the chips Midway's board and most of its bootlegs share
+const PACMAN_6E: Dump = Dump {
+    file_name: "pacman.6e",
+    size: 0x1000,
+    crc32: 0xC1E6_AB10,
+    sha1: Some("e87e059c5be45753f7e9f33dff851f16d6751181"),
+    region: Region::Program,
+    offset: 0,
+};
+
+const PACMAN_6F: Dump = Dump {
+    file_name: "pacman.6f",
+    size: 0x1000,
+    crc32: 0x1A6F_B2D4,
+    sha1: Some("674d3a7f00d8be5e38b1fdc208ebef5a92d38329"),
+    region: Region::Program,
+    offset: 0x1000,
+};
+
+const PACMAN_6H: Dump = Dump {
+    file_name: "pacman.6h",
+    size: 0x1000,
+    crc32: 0xBCDD_1BEB,
+    sha1: Some("8e47e8c2c4d6117d174cdac150392042d3e0a881"),
+    region: Region::Program,
+    offset: 0x2000,
+};
+
+const PACMAN_6J: Dump = Dump {
+    file_name: "pacman.6j",
+    size: 0x1000,
+    crc32: 0x817D_94E3,
+    sha1: Some("d4a70d56bb01d27d094d73db8667ffb00ca69cb9"),
+    region: Region::Program,
+    offset: 0x3000,
+};
+
+const COLOR_PROM: Dump = Dump {
+    file_name: "82s123.7f",
+    size: 0x20,
+    crc32: 0x2FC6_50BD,
+    sha1: Some("8d0268dee78e47c712202b0ec4f1f51109b1f2a5"),
+    region: Region::Colors,
+    offset: 0,
+};
+
+const PALETTE_PROM: Dump = Dump {
+    file_name: "82s126.4a",
+    size: 0x100,
+    crc32: 0x3EB3_A8E4,
+    sha1: Some("19097b5f60d1030f8b82d9f1d3a241f93e5c75d6"),
+    region: Region::Palettes,
+    offset: 0,
+};
+
+const PACMAN_5E: Dump = Dump {
+    file_name: "pacman.5e",
+    size: 0x1000,
+    crc32: 0x0C94_4964,
+    sha1: Some("06ef227747a440831c9a3a613b76693d52a2f0a9"),
+    region: Region::Tiles,
+    offset: 0,
+};
+
+const PACMAN_5F: Dump = Dump {
+    file_name: "pacman.5f",
+    size: 0x1000,
+    crc32: 0x958F_EDF9,
+    sha1: Some("4a937ac02216ea8c96477d4a15522070507fb599"),
+    region: Region::Sprites,
+    offset: 0,
+};
+
+const WAVEFORM_PROM: Dump = Dump {
+    file_name: "82s126.1m",
+    size: 0x100,
+    crc32: 0xA9CC_86BF,
+    sha1: Some("bbcec0570aeceb582ff8238a4bc8546a23430081"),
+    region: Region::Sound,
+    offset: 0,
+};
+
+const TIMING_PROM: Dump = Dump {
+    file_name: "82s126.3m",
+    size: 0x100,
+    crc32: 0x7724_5B66,
+    sha1: Some("0c4d0bee858b97632411c440bea6948a74759746"),
+    region: Region::Sound,
+    offset: 0x100,
+};
+
+pub const SETS: &[RomSet] = &[
+    RomSet {
+        name: "pacman",
+        description: "Pac-Man (Midway)",
+        parent: Some("puckman"),
+        dumps: &[
+            PACMAN_6E,
+            PACMAN_6F,
+            PACMAN_6H,
+            PACMAN_6J,
+            COLOR_PROM,
+            PALETTE_PROM,
+            PACMAN_5E,
+            PACMAN_5F,
+            WAVEFORM_PROM,
+            TIMING_PROM,
+        ],
+    },
+    RomSet {
+        name: "puckman",
+        description: "Puck Man (Japan set 1)",
+        parent: None,
+        dumps: &[
+            Dump {
+                file_name: "pm1_prg1.6e",
+                size: 0x800,
+                crc32: 0xF36E_88AB,
+                sha1: Some("813cecf44bf5464b1aed64b36f5047e4c79ba176"),
+                region: Region::Program,
+                offset: 0,
+            },
+            Dump {
+                file_name: "pm1_prg2.6k",
+                size: 0x800,
+                crc32: 0x618B_D9B3,
+                sha1: Some("b9ca52b63a49ddece768378d331deebbe34fe177"),
+                region: Region::Program,
+                offset: 0x800,
+            },
+            Dump {
+                file_name: "pm1_prg3.6f",
+                size: 0x800,
+                crc32: 0x7D17_7853,
+                sha1: Some("9b5ddaaa8b564654f97af193dbcc29f81f230a25"),
+                region: Region::Program,
+                offset: 0x1000,
+            },
+            Dump {
+                file_name: "pm1_prg4.6m",
+                size: 0x800,
+                crc32: 0xD3E8_914C,
+                sha1: Some("c2f00e1773c6864435f29c8b7f44f2ef85d227d3"),
+                region: Region::Program,
+                offset: 0x1800,
+            },
+            Dump {
+                file_name: "pm1_prg5.6h",
+                size: 0x800,
+                crc32: 0x6BF4_F625,
+                sha1: Some("afe72fdfec66c145b53ed865f98734686b26e921"),
+                region: Region::Program,
+                offset: 0x2000,
+            },
+            Dump {
+                file_name: "pm1_prg6.6n",
+                size: 0x800,
+                crc32: 0xA948_CE83,
+                sha1: Some("08759833f7e0690b2ccae573c929e2a48e5bde7f"),
+                region: Region::Program,
+                offset: 0x2800,
+            },
+            Dump {
+                file_name: "pm1_prg7.6j",
+                size: 0x800,
+                crc32: 0xB628_9B26,
+                sha1: Some("d249fa9cdde774d5fee7258147cd25fa3f4dc2b3"),
+                region: Region::Program,
+                offset: 0x3000,
+            },
+            Dump {
+                file_name: "pm1_prg8.6p",
+                size: 0x800,
+                crc32: 0x17A8_8C13,
+                sha1: Some("eb462de79f49b7aa8adb0cc6d31535b10550c0ce"),
+                region: Region::Program,
+                offset: 0x3800,
+            },
+            Dump {
+                file_name: "pm1-1.7f",
+                size: 0x20,
+                crc32: 0x2FC6_50BD,
+                sha1: Some("8d0268dee78e47c712202b0ec4f1f51109b1f2a5"),
+                region: Region::Colors,
+                offset: 0,
+            },
+            Dump {
+                file_name: "pm1-4.4a",
+                size: 0x100,
+                crc32: 0x3EB3_A8E4,
+                sha1: Some("19097b5f60d1030f8b82d9f1d3a241f93e5c75d6"),
+                region: Region::Palettes,
+                offset: 0,
+            },
+            Dump {
+                file_name: "pm1_chg1.5e",
+                size: 0x800,
+                crc32: 0x2066_A0B7,
+                sha1: Some("6d4ccc27d6be185589e08aa9f18702b679e49a4a"),
+                region: Region::Tiles,
+                offset: 0,
+            },
+            Dump {
+                file_name: "pm1_chg2.5h",
+                size: 0x800,
+                crc32: 0x3591_B89D,
+                sha1: Some("79bb456be6c39c1ccd7d077fbe181523131fb300"),
+                region: Region::Tiles,
+                offset: 0x800,
+            },
+            Dump {
+                file_name: "pm1_chg3.5f",
+                size: 0x800,
+                crc32: 0x9E39_323A,
+                sha1: Some("be933e691df4dbe7d12123913c3b7b7b585b7a35"),
+                region: Region::Sprites,
+                offset: 0,
+            },
+            Dump {
+                file_name: "pm1_chg4.5j",
+                size: 0x800,
+                crc32: 0x1B1D_9096,
+                sha1: Some("53771c573051db43e7185b1d188533056290a620"),
+                region: Region::Sprites,
+                offset: 0x800,
+            },
+            Dump {
+                file_name: "pm1-3.1m",
+                size: 0x100,
+                crc32: 0xA9CC_86BF,
+                sha1: Some("bbcec0570aeceb582ff8238a4bc8546a23430081"),
+                region: Region::Sound,
+                offset: 0,
+            },
+            Dump {
+                file_name: "pm1-2.3m",
+                size: 0x100,
+                crc32: 0x7724_5B66,
+                sha1: Some("0c4d0bee858b97632411c440bea6948a74759746"),
+                region: Region::Sound,
+                offset: 0x100,
+            },
+        ],
+    },
+    RomSet {
+        name: "pacmanf",
+        description: "Pac-Man (Midway, with speedup hack)",
+        parent: Some("puckman"),
+        dumps: &[
+            PACMAN_6E,
+            Dump {
+                file_name: "pacfast.6f",
+                size: 0x1000,
+                crc32: 0x720D_C3EE,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x1000,
+            },
+            PACMAN_6H,
+            PACMAN_6J,
+            COLOR_PROM,
+            PALETTE_PROM,
+            PACMAN_5E,
+            PACMAN_5F,
+            WAVEFORM_PROM,
+            TIMING_PROM,
+        ],
+    },
+    RomSet {
+        name: "hangly",
+        description: "Hangly-Man (set 1)",
+        parent: Some("puckman"),
+        dumps: &[
+            Dump {
+                file_name: "hangly.6e",
+                size: 0x1000,
+                crc32: 0x5FE8_610A,
+                sha1: None,
+                region: Region::Program,
+                offset: 0,
+            },
+            Dump {
+                file_name: "hangly.6f",
+                size: 0x1000,
+                crc32: 0x7372_6586,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x1000,
+            },
+            Dump {
+                file_name: "hangly.6h",
+                size: 0x1000,
+                crc32: 0x4E7E_F99F,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x2000,
+            },
+            Dump {
+                file_name: "hangly.6j",
+                size: 0x1000,
+                crc32: 0x7F41_47E6,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x3000,
+            },
+            COLOR_PROM,
+            PALETTE_PROM,
+            PACMAN_5E,
+            PACMAN_5F,
+            WAVEFORM_PROM,
+            TIMING_PROM,
+        ],
+    },
+    RomSet {
+        name: "puckmanb",
+        description: "Puck Man (bootleg set 1)",
+        parent: Some("puckman"),
+        dumps: &[
+            Dump {
+                file_name: "namcopac.6e",
+                size: 0x1000,
+                crc32: 0xFEE2_63B3,
+                sha1: None,
+                region: Region::Program,
+                offset: 0,
+            },
+            Dump {
+                file_name: "namcopac.6f",
+                size: 0x1000,
+                crc32: 0x39D1_FC83,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x1000,
+            },
+            Dump {
+                file_name: "namcopac.6h",
+                size: 0x1000,
+                crc32: 0x0208_3B03,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x2000,
+            },
+            Dump {
+                file_name: "namcopac.6j",
+                size: 0x1000,
+                crc32: 0x7A36_FE55,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x3000,
+            },
+            COLOR_PROM,
+            PALETTE_PROM,
+            PACMAN_5E,
+            PACMAN_5F,
+            WAVEFORM_PROM,
+            TIMING_PROM,
+        ],
+    },
+    RomSet {
+        name: "pacmod",
+        description: "Pac-Man (Midway, harder)",
+        parent: Some("puckman"),
+        dumps: &[
+            Dump {
+                file_name: "pacmanh.6e",
+                size: 0x1000,
+                crc32: 0x3B2E_C270,
+                sha1: None,
+                region: Region::Program,
+                offset: 0,
+            },
+            PACMAN_6F,
+            Dump {
+                file_name: "pacmanh.6h",
+                size: 0x1000,
+                crc32: 0x1881_1780,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x2000,
+            },
+            Dump {
+                file_name: "pacmanh.6j",
+                size: 0x1000,
+                crc32: 0x5C96_A733,
+                sha1: None,
+                region: Region::Program,
+                offset: 0x3000,
+            },
+            COLOR_PROM,
+            PALETTE_PROM,
+            Dump {
+                file_name: "pacmanh.5e",
+                size: 0x1000,
+                crc32: 0x299F_B17A,
+                sha1: None,
+                region: Region::Tiles,
+                offset: 0,
+            },
+            PACMAN_5F,
+            WAVEFORM_PROM,
+            TIMING_PROM,
+        ],
+    },
+];
+
+/// The set called <name>
+pub fn find_set(name: &str) -> Option<&'static RomSet> {
+    SETS.iter().find(|set| set.name == name)
+}
+
+impl RomSet {
+    /// The dump that should be in the file called <file_name>
+    pub fn dump(&self, file_name: &str) -> Option<&'static Dump> {
+        self.dumps.iter().find(|dump| dump.file_name == file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let pacman = find_set("pacman").unwrap();
+        assert_eq!(pacman.dump("pacman.6e").unwrap().crc32, 0xC1E6_AB10);
+        assert!(pacman.dump("pm1_prg1.6e").is_none());
+        assert!(find_set("galaga").is_none());
+    }
+
+    #[test]
+    fn test_sets() {
+        for set in SETS {
+            for dump in set.dumps {
+                if let Some(sha1) = dump.sha1 {
+                    assert_eq!(sha1.len(), 40, "{}", dump.file_name);
+                }
+                assert_eq!(set.dump(dump.file_name).unwrap().crc32, dump.crc32);
+            }
+            assert!(set.parent.map_or(true, |parent| find_set(parent).is_some()));
+        }
+        // the program is in 8 smaller chips on Namco's board
+        let puckman = find_set("puckman").unwrap();
+        let program: usize = puckman.dumps[..8].iter().map(|dump| dump.size).sum();
+        assert_eq!(program, 0x4000);
+    }
+
+    #[test]
+    fn test_layouts() {
+        // every byte of every region comes from exactly one chip
+        for set in SETS {
+            for &region in REGIONS.iter() {
+                let mut loaded = vec![0; region.size()];
+                for dump in set.dumps.iter().filter(|dump| dump.region == region) {
+                    for byte in &mut loaded[dump.offset..dump.offset + dump.size] {
+                        *byte += 1;
+                    }
+                }
+                assert!(loaded.iter().all(|&count| count == 1), "{}", set.name);
+            }
+        }
+    }
+}
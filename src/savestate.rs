@@ -0,0 +1,134 @@
+// save states are a flat little endian dump of every component, in a fixed
+// order, after this header. the version goes up whenever the layout changes
+const MAGIC: &[u8; 4] = b"PMSS";
+const VERSION: u8 = 1;
+
+/// Builds a save state
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        StateWriter { data: data }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&[value as u8, (value >> 8) as u8]);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.u16(value as u16);
+        self.u16((value >> 16) as u16);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads back what a StateWriter wrote, in the same order
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, String> {
+        if data.len() < 5 || &data[..4] != MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        if data[4] != VERSION {
+            return Err(format!("Unsupported save state version {}", data[4]));
+        }
+        Ok(StateReader { data: &data[5..] })
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let low = self.u16()? as u32;
+        Ok(low | (self.u16()? as u32) << 16)
+    }
+
+    /// The next <len> bytes
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("Save state is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Fails if anything is left over, which means the state doesn't match
+    /// what was read from it
+    pub fn finish(self) -> Result<(), String> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err("Save state is too long".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u32(0x789A_BCDE);
+        writer.bytes(&[1, 2, 3]);
+        let data = writer.finish();
+        assert_eq!(data.len(), 5 + 1 + 1 + 2 + 4 + 3);
+
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.bytes(3), Ok(&[1, 2, 3][..]));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_states() {
+        assert!(StateReader::new(b"PMS").is_err());
+        assert!(StateReader::new(b"XXXX\x01").is_err());
+        assert!(StateReader::new(b"PMSS\x02").is_err());
+
+        let mut reader = StateReader::new(b"PMSS\x01\x12").unwrap();
+        assert!(reader.u16().is_err());
+        let reader = StateReader::new(b"PMSS\x01\x12").unwrap();
+        assert!(reader.finish().is_err());
+    }
+}
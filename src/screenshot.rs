@@ -0,0 +1,154 @@
+use checksum::{crc32, update_crc32};
+use display::{Display, HEIGHT, PITCH, WIDTH};
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// the most a stored (uncompressed) deflate block can hold
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/// Writes the current frame of <display> to <path>
+pub fn save(display: &Display, path: &Path, format: ImageFormat) -> io::Result<()> {
+    let bytes = match format {
+        ImageFormat::Ppm => ppm(display.pixels()),
+        ImageFormat::Png => png(display.pixels()),
+    };
+    File::create(path)?.write_all(&bytes)
+}
+
+// binary PPM, which is just a header in front of the RGB24 pixels
+fn ppm(pixels: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+    bytes.extend_from_slice(pixels);
+    bytes
+}
+
+// an RGB PNG. the image data isn't compressed, which keeps this simple and
+// any PNG reader can still load it
+fn png(pixels: &[u8]) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&be32(WIDTH as u32));
+    header.extend_from_slice(&be32(HEIGHT as u32));
+    // 8 bits per channel, truecolour, deflate, no filtering, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // every row starts with its filter type, 0 for none
+    let mut data = Vec::with_capacity((PITCH + 1) * HEIGHT);
+    for row in pixels.chunks(PITCH) {
+        data.push(0);
+        data.extend_from_slice(row);
+    }
+
+    let mut bytes = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut bytes, b"IHDR", &header);
+    write_chunk(&mut bytes, b"IDAT", &zlib_stored(&data));
+    write_chunk(&mut bytes, b"IEND", &[]);
+    bytes
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&be32(data.len() as u32));
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(&be32(update_crc32(crc32(kind), data)));
+}
+
+// wraps <data> in a zlib stream of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary, fastest
+    let mut bytes = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    while let Some(block) = blocks.next() {
+        // the first bit marks the last block, the next two are 0 for stored
+        let last = blocks.peek().is_none();
+        bytes.push(last as u8);
+        let len = block.len() as u16;
+        bytes.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+        bytes.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+        bytes.extend_from_slice(block);
+    }
+
+    bytes.extend_from_slice(&be32(adler32(data)));
+    bytes
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn be32(value: u32) -> [u8; 4] {
+    [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ppm() {
+        let pixels = vec![7; PITCH * HEIGHT];
+        let bytes = ppm(&pixels);
+
+        assert!(bytes.starts_with(b"P6\n224 288\n255\n"));
+        assert_eq!(bytes.len(), 15 + PITCH * HEIGHT);
+    }
+
+    #[test]
+    fn test_png() {
+        let pixels = vec![7; PITCH * HEIGHT];
+        let bytes = png(&pixels);
+
+        assert!(bytes.starts_with(&PNG_SIGNATURE));
+        assert_eq!(&bytes[12..16], b"IHDR");
+        assert_eq!(&bytes[16..24], &[0, 0, 0, 224, 0, 0, 1, 32]);
+        assert!(bytes.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let data = vec![1; MAX_STORED_BLOCK + 10];
+        let bytes = zlib_stored(&data);
+        // header, two blocks with 5 byte headers, checksum
+        assert_eq!(bytes.len(), 2 + 5 + MAX_STORED_BLOCK + 5 + 10 + 4);
+        assert_eq!(bytes[2], 0);
+        assert_eq!(bytes[2 + 5 + MAX_STORED_BLOCK], 1);
+    }
+}
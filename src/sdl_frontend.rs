@@ -0,0 +1,315 @@
+use audio::AudioOutput;
+use display::{Display, VideoSettings, HEIGHT, PITCH, WIDTH};
+use frame_pacer::{FramePacer, Speed};
+use frontend::Frontend;
+use input::{Button, Input};
+use pacman::PacmanSystem;
+use sound::AudioMode;
+
+use sdl2;
+use sdl2::controller::{self, Axis, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, GameControllerSubsystem};
+
+// how far an analog stick has to be pushed to count as a joystick direction
+const AXIS_THRESHOLD: i16 = 16384;
+
+/// Runs <pacman> in a window, with sound and keyboard/controller input,
+/// until the window is closed
+pub fn run(
+    pacman: &mut PacmanSystem,
+    video: VideoSettings,
+    audio_mode: AudioMode,
+) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let mut window = video_subsystem.window(
+        "Pacman",
+        WIDTH as u32 * video.scale,
+        HEIGHT as u32 * video.scale,
+    );
+    window.position_centered().resizable();
+    if video.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().map_err(|why| why.to_string())?;
+
+    let audio = match audio_mode {
+        AudioMode::Disabled => None,
+        mode => match AudioOutput::new(&sdl_context, mode == AudioMode::Muted) {
+            Ok(audio) => Some(audio),
+            Err(why) => {
+                eprintln!("Couldn't open audio device, running without sound: {}", why);
+                None
+            }
+        },
+    };
+
+    let canvas = window
+        .into_canvas()
+        .build()
+        .map_err(|why| why.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+        .map_err(|why| why.to_string())?;
+
+    let mut frontend = SdlFrontend {
+        controller_subsystem: sdl_context.game_controller()?,
+        controllers: Vec::new(),
+        event_pump: sdl_context.event_pump()?,
+        canvas: canvas,
+        texture: texture,
+        audio: audio,
+        video: video,
+        pacer: FramePacer::new(),
+        quit: false,
+    };
+    pacman.run(&mut frontend)
+}
+
+// the window, speakers, keyboard and controllers. the texture is borrowed
+// from a TextureCreator that run keeps alive
+struct SdlFrontend<'t> {
+    controller_subsystem: GameControllerSubsystem,
+    // the first controller plugged in is player one, the second player two
+    controllers: Vec<GameController>,
+    event_pump: EventPump,
+    canvas: Canvas<Window>,
+    texture: Texture<'t>,
+    audio: Option<AudioOutput>,
+    video: VideoSettings,
+    pacer: FramePacer,
+    quit: bool,
+}
+
+impl<'t> SdlFrontend<'t> {
+    fn toggle_fullscreen(&mut self) {
+        self.video.fullscreen = !self.video.fullscreen;
+        let fullscreen = if self.video.fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        if let Err(why) = self.canvas.window_mut().set_fullscreen(fullscreen) {
+            eprintln!("Couldn't toggle fullscreen: {}", why);
+        }
+    }
+}
+
+impl<'t> Frontend for SdlFrontend<'t> {
+    fn poll_input(&mut self, input: &mut Input) {
+        // collected first, handling them needs the rest of self
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit { .. } => self.quit = true,
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = self.controller_subsystem.open(which) {
+                        self.controllers.push(controller);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|c| c.instance_id() != which);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    if let Some(ref mut audio) = self.audio {
+                        audio.toggle_mute();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => self.pacer.toggle(Speed::Turbo),
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    ..
+                } => self.pacer.toggle(Speed::SlowMotion),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => self.toggle_fullscreen(),
+                _ => handle_input(input, &event, &self.controllers),
+            }
+        }
+    }
+
+    fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    // a frame that can't be drawn is skipped rather than ending the game
+    fn present(&mut self, display: &Display) -> Result<(), String> {
+        let shown = show(
+            display,
+            &mut self.canvas,
+            &mut self.texture,
+            self.video.integer_scaling,
+        );
+        if let Err(why) = shown {
+            eprintln!("Couldn't draw frame: {}", why);
+        }
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn queue_audio(&mut self, samples: &[i16]) {
+        if let Some(ref mut audio) = self.audio {
+            audio.push(samples);
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.pacer.wait();
+    }
+}
+
+// uploads the frame to <texture>, an RGB24 streaming texture of the screen's
+// size, and copies it to the middle of <canvas> with black bars around it
+fn show(
+    display: &Display,
+    canvas: &mut Canvas<Window>,
+    texture: &mut Texture,
+    integer_scaling: bool,
+) -> Result<(), String> {
+    texture
+        .update(None, display.pixels(), PITCH)
+        .map_err(|why| why.to_string())?;
+
+    let (width, height) = canvas.output_size()?;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.copy(texture, None, letterbox(width, height, integer_scaling))
+}
+
+// the largest rectangle with the screen's aspect ratio that fits a
+// <width>x<height> window, centered. with integer scaling it's shrunk to a
+// whole multiple of the screen size if the window is big enough for one
+fn letterbox(width: u32, height: u32, integer_scaling: bool) -> Rect {
+    let fit = (width as f64 / WIDTH as f64).min(height as f64 / HEIGHT as f64);
+    let scale = if integer_scaling && fit >= 1.0 {
+        fit.floor()
+    } else {
+        fit
+    };
+
+    let scaled_width = (WIDTH as f64 * scale) as u32;
+    let scaled_height = (HEIGHT as f64 * scale) as u32;
+    Rect::new(
+        ((width - scaled_width) / 2) as i32,
+        ((height - scaled_height) / 2) as i32,
+        scaled_width,
+        scaled_height,
+    )
+}
+
+// feeds keyboard and controller events into IN0 and IN1
+fn handle_input(input: &mut Input, event: &Event, controllers: &Vec<GameController>) {
+    let player = |which: i32| controllers.iter().position(|c| c.instance_id() == which);
+
+    match *event {
+        Event::KeyDown {
+            keycode: Some(key), ..
+        } => {
+            if let Some(button) = key_button(key) {
+                input.press(button);
+            }
+        }
+        Event::KeyUp {
+            keycode: Some(key), ..
+        } => {
+            if let Some(button) = key_button(key) {
+                input.release(button);
+            }
+        }
+        Event::ControllerButtonDown { which, button, .. } => {
+            if let Some(button) = player(which).and_then(|p| controller_button(p, button)) {
+                input.press(button);
+            }
+        }
+        Event::ControllerButtonUp { which, button, .. } => {
+            if let Some(button) = player(which).and_then(|p| controller_button(p, button)) {
+                input.release(button);
+            }
+        }
+        Event::ControllerAxisMotion {
+            which, axis, value, ..
+        } => {
+            let (negative, positive) = match (player(which), axis) {
+                (Some(0), Axis::LeftX) => (Button::PlayerOneLeft, Button::PlayerOneRight),
+                (Some(0), Axis::LeftY) => (Button::PlayerOneUp, Button::PlayerOneDown),
+                (Some(1), Axis::LeftX) => (Button::PlayerTwoLeft, Button::PlayerTwoRight),
+                (Some(1), Axis::LeftY) => (Button::PlayerTwoUp, Button::PlayerTwoDown),
+                _ => return,
+            };
+            input.set(negative, value < -AXIS_THRESHOLD);
+            input.set(positive, value > AXIS_THRESHOLD);
+        }
+        _ => {}
+    }
+}
+
+// keyboard layout matches MAME's defaults
+fn key_button(key: Keycode) -> Option<Button> {
+    match key {
+        Keycode::Up => Some(Button::PlayerOneUp),
+        Keycode::Left => Some(Button::PlayerOneLeft),
+        Keycode::Right => Some(Button::PlayerOneRight),
+        Keycode::Down => Some(Button::PlayerOneDown),
+        Keycode::R => Some(Button::PlayerTwoUp),
+        Keycode::D => Some(Button::PlayerTwoLeft),
+        Keycode::G => Some(Button::PlayerTwoRight),
+        Keycode::F => Some(Button::PlayerTwoDown),
+        Keycode::Num1 => Some(Button::StartOne),
+        Keycode::Num2 => Some(Button::StartTwo),
+        Keycode::Num5 => Some(Button::CoinOne),
+        Keycode::Num6 => Some(Button::CoinTwo),
+        Keycode::Num9 => Some(Button::Service),
+        Keycode::F1 => Some(Button::RackTest),
+        Keycode::F2 => Some(Button::Test),
+        _ => None,
+    }
+}
+
+// d-pad moves, start starts and back inserts a coin for the controller's
+// player
+fn controller_button(player: usize, button: controller::Button) -> Option<Button> {
+    match (player, button) {
+        (0, controller::Button::DPadUp) => Some(Button::PlayerOneUp),
+        (0, controller::Button::DPadLeft) => Some(Button::PlayerOneLeft),
+        (0, controller::Button::DPadRight) => Some(Button::PlayerOneRight),
+        (0, controller::Button::DPadDown) => Some(Button::PlayerOneDown),
+        (0, controller::Button::Start) => Some(Button::StartOne),
+        (0, controller::Button::Back) => Some(Button::CoinOne),
+        (1, controller::Button::DPadUp) => Some(Button::PlayerTwoUp),
+        (1, controller::Button::DPadLeft) => Some(Button::PlayerTwoLeft),
+        (1, controller::Button::DPadRight) => Some(Button::PlayerTwoRight),
+        (1, controller::Button::DPadDown) => Some(Button::PlayerTwoDown),
+        (1, controller::Button::Start) => Some(Button::StartTwo),
+        (1, controller::Button::Back) => Some(Button::CoinTwo),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_letterbox() {
+        assert_eq!(letterbox(448, 576, true), Rect::new(0, 0, 448, 576));
+        // widescreen, bars on the sides
+        assert_eq!(letterbox(1920, 1080, true), Rect::new(624, 108, 672, 864));
+        assert_eq!(letterbox(1920, 1080, false), Rect::new(540, 0, 840, 1080));
+        // too small for whole multiples
+        assert_eq!(letterbox(112, 200, true), Rect::new(0, 28, 112, 144));
+    }
+}
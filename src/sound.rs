@@ -0,0 +1,308 @@
+use savestate::{StateReader, StateWriter};
+
+/// Rate the WSG produces samples at: the 3.072 MHz CPU clock divided by 32
+pub const SAMPLE_RATE: u32 = 96000;
+
+const CYCLES_PER_SAMPLE: usize = 32;
+// oldest samples are dropped once this many are waiting to be taken, so
+// nothing piles up when no one is listening
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize;
+// a voice's 4 bit sample (centered on 0) times its 4 bit volume is at most
+// 120, so three of them scaled by this stay well inside an i16
+const OUTPUT_SCALE: i32 = 64;
+
+/// What the frontend does with the sound
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioMode {
+    Enabled,
+    // the device is opened and fed silence, M toggles sound back on
+    Muted,
+    // no audio device at all, for running headless
+    Disabled,
+}
+
+#[derive(Clone, Copy)]
+struct Voice {
+    // 20 bits for voice one, the other two don't have the lowest nibble
+    accumulator: u32,
+    frequency: u32,
+    waveform: u8,
+    volume: u8,
+}
+
+impl Voice {
+    fn new() -> Self {
+        Voice {
+            accumulator: 0,
+            frequency: 0,
+            waveform: 0,
+            volume: 0,
+        }
+    }
+}
+
+/// The Namco Waveform Sound Generator: three voices playing 32 sample 4 bit
+/// waveforms from the sound PROM
+///
+/// It is programmed through 32 nibble wide registers at 0x5040-0x505F:
+///
+/// 0x00-0x04: voice 1 accumulator, 0x05: voice 1 waveform
+/// 0x06-0x09: voice 2 accumulator, 0x0A: voice 2 waveform
+/// 0x0B-0x0E: voice 3 accumulator, 0x0F: voice 3 waveform
+/// 0x10-0x14: voice 1 frequency, 0x15: voice 1 volume
+/// 0x16-0x19: voice 2 frequency, 0x1A: voice 2 volume
+/// 0x1B-0x1E: voice 3 frequency, 0x1F: voice 3 volume
+///
+/// Multi nibble values are written lowest nibble first.
+pub struct Wsg {
+    waveforms: [u8; 256],
+    voices: [Voice; 3],
+    enabled: bool,
+    // cycles that didn't add up to a whole sample yet
+    cycles: usize,
+    samples: Vec<i16>,
+}
+
+impl Wsg {
+    pub fn new(waveforms: &[u8; 256]) -> Self {
+        Wsg {
+            waveforms: *waveforms,
+            voices: [Voice::new(); 3],
+            enabled: false,
+            cycles: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Writes to register <offset>. Only the low nibble of <byte> is used
+    pub fn write(&mut self, offset: usize, byte: u8) {
+        let nibble = (byte & 0x0F) as u32;
+
+        match offset {
+            0x00...0x04 => set_nibble(&mut self.voices[0].accumulator, offset, nibble),
+            0x05 => self.voices[0].waveform = (nibble & 0x07) as u8,
+            0x06...0x09 => set_nibble(&mut self.voices[1].accumulator, offset - 0x05, nibble),
+            0x0A => self.voices[1].waveform = (nibble & 0x07) as u8,
+            0x0B...0x0E => set_nibble(&mut self.voices[2].accumulator, offset - 0x0A, nibble),
+            0x0F => self.voices[2].waveform = (nibble & 0x07) as u8,
+            0x10...0x14 => set_nibble(&mut self.voices[0].frequency, offset - 0x10, nibble),
+            0x15 => self.voices[0].volume = nibble as u8,
+            0x16...0x19 => set_nibble(&mut self.voices[1].frequency, offset - 0x15, nibble),
+            0x1A => self.voices[1].volume = nibble as u8,
+            0x1B...0x1E => set_nibble(&mut self.voices[2].frequency, offset - 0x1A, nibble),
+            0x1F => self.voices[2].volume = nibble as u8,
+            _ => {}
+        }
+    }
+
+    /// Set by bit 0 of 0x5001. Voices keep running while disabled but the
+    /// output is silent
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Advances the WSG by <cycles> CPU cycles, producing a 96 kHz sample
+    /// every 32 of them
+    pub fn run(&mut self, cycles: usize) {
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SAMPLE {
+            self.cycles -= CYCLES_PER_SAMPLE;
+            let sample = self.sample();
+            self.samples.push(sample);
+        }
+
+        if self.samples.len() > MAX_BUFFERED_SAMPLES {
+            let excess = self.samples.len() - MAX_BUFFERED_SAMPLES;
+            self.samples.drain(..excess);
+        }
+    }
+
+    /// Appends the voices to <state>. The waveform PROM isn't included, and
+    /// samples that weren't taken yet are dropped
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for voice in self.voices.iter() {
+            state.u32(voice.accumulator);
+            state.u32(voice.frequency);
+            state.u8(voice.waveform);
+            state.u8(voice.volume);
+        }
+        state.bool(self.enabled);
+        state.u8(self.cycles as u8);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for voice in self.voices.iter_mut() {
+            voice.accumulator = state.u32()? & 0xFFFFF;
+            voice.frequency = state.u32()? & 0xFFFFF;
+            voice.waveform = state.u8()? & 0x07;
+            voice.volume = state.u8()? & 0x0F;
+        }
+        self.enabled = state.bool()?;
+        self.cycles = state.u8()? as usize % CYCLES_PER_SAMPLE;
+        self.samples.clear();
+        Ok(())
+    }
+
+    /// Returns the samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        let mut samples = Vec::with_capacity(self.samples.len());
+        samples.append(&mut self.samples);
+        samples
+    }
+
+    // steps each voice once and mixes them
+    fn sample(&mut self) -> i16 {
+        let mut mix: i32 = 0;
+        for voice in self.voices.iter_mut() {
+            voice.accumulator = (voice.accumulator + voice.frequency) & 0xFFFFF;
+            // the top 5 bits of the accumulator pick one of 32 samples
+            let index = voice.waveform as usize * 32 + (voice.accumulator >> 15) as usize;
+            let sample = (self.waveforms[index] & 0x0F) as i32 - 8;
+            mix += sample * voice.volume as i32;
+        }
+
+        if self.enabled {
+            (mix * OUTPUT_SCALE) as i16
+        } else {
+            0
+        }
+    }
+}
+
+fn set_nibble(word: &mut u32, index: usize, nibble: u32) {
+    let shift = index * 4;
+    *word = (*word & !(0x0F << shift)) | (nibble << shift);
+}
+
+/// Converts a stream of samples to another sample rate by linear
+/// interpolation. Keeps its position between calls so that a stream can be
+/// converted in chunks of any size
+pub struct Resampler {
+    // input samples per output sample
+    step: f64,
+    // position of the next output sample, where 0 is the last sample of the
+    // previous chunk and 1 the first of the current one
+    position: f64,
+    last: i16,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampler {
+            step: from_rate as f64 / to_rate as f64,
+            position: 1.0,
+            last: 0,
+        }
+    }
+
+    /// Changes the conversion ratio, used to nudge the output rate for drift
+    /// correction
+    pub fn set_rates(&mut self, from_rate: f64, to_rate: f64) {
+        self.step = from_rate / to_rate;
+    }
+
+    /// Appends <input> converted to the output rate to <output>
+    pub fn resample(&mut self, input: &[i16], output: &mut Vec<i16>) {
+        if input.is_empty() {
+            return;
+        }
+
+        let len = input.len() as f64;
+        while self.position < len {
+            let index = self.position as usize;
+            let fraction = self.position - index as f64;
+
+            let a = if index == 0 {
+                self.last
+            } else {
+                input[index - 1]
+            } as f64;
+            let b = input[index] as f64;
+            output.push((a + (b - a) * fraction).round() as i16);
+
+            self.position += self.step;
+        }
+
+        self.position -= len;
+        self.last = input[input.len() - 1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // waveform 0 is a square wave, waveform 1 is silent
+    fn waveforms() -> [u8; 256] {
+        let mut waveforms = [8; 256];
+        for i in 0..32 {
+            waveforms[i] = if i < 16 { 15 } else { 0 };
+        }
+        waveforms
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut wsg = Wsg::new(&waveforms());
+
+        // voice 1 frequency 0x12345, voice 2 frequency 0x6780
+        for (i, nibble) in [5, 4, 3, 2, 1].iter().enumerate() {
+            wsg.write(0x10 + i, *nibble);
+        }
+        for (i, nibble) in [8, 7, 6].iter().enumerate() {
+            wsg.write(0x16 + i, *nibble);
+        }
+        wsg.write(0x1A, 0xF3);
+        wsg.write(0x0A, 0x0F);
+
+        assert_eq!(wsg.voices[0].frequency, 0x12345);
+        assert_eq!(wsg.voices[1].frequency, 0x0678 << 4);
+        assert_eq!(wsg.voices[1].volume, 3);
+        assert_eq!(wsg.voices[1].waveform, 7);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut wsg = Wsg::new(&waveforms());
+
+        // a frequency of 0x8000 steps through one waveform sample at a time
+        wsg.write(0x13, 0x08);
+        wsg.write(0x15, 0x0F);
+        wsg.run(32 * 64);
+        assert!(wsg.take_samples().iter().all(|s| *s == 0));
+
+        wsg.set_enabled(true);
+        wsg.run(32 * 32 + 31);
+        let samples = wsg.take_samples();
+        assert_eq!(samples.len(), 32);
+        assert_eq!(samples[0], 7 * 15 * OUTPUT_SCALE as i16);
+        assert_eq!(samples[16], -8 * 15 * OUTPUT_SCALE as i16);
+        assert!(wsg.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_buffer_limit() {
+        let mut wsg = Wsg::new(&waveforms());
+
+        wsg.run(CYCLES_PER_SAMPLE * (MAX_BUFFERED_SAMPLES + 100));
+        assert_eq!(wsg.take_samples().len(), MAX_BUFFERED_SAMPLES);
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut resampler = Resampler::new(96000, 48000);
+        let mut output = Vec::new();
+
+        let input: Vec<i16> = (0..960).map(|i| i as i16).collect();
+        resampler.resample(&input[..500], &mut output);
+        resampler.resample(&input[500..], &mut output);
+        assert_eq!(output.len(), 480);
+        // every other sample, whatever the chunk boundaries
+        assert!(output.iter().enumerate().all(|(i, s)| *s == i as i16 * 2));
+
+        let mut resampler = Resampler::new(2, 3);
+        let mut output = Vec::new();
+        resampler.resample(&[0, 30, 60, 90], &mut output);
+        assert_eq!(output, vec![0, 20, 40, 60, 80]);
+    }
+}
@@ -0,0 +1,253 @@
+use checksum::crc32;
+use inflate::inflate;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4B50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4B50;
+const END_OF_DIRECTORY_SIGNATURE: u32 = 0x0605_4B50;
+// the end of central directory record, not counting its comment
+const END_OF_DIRECTORY_SIZE: usize = 22;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// A file in a zip archive, as listed by the central directory
+pub struct Entry {
+    /// Without any directories it's in
+    pub name: String,
+    pub crc32: u32,
+    pub size: usize,
+    compressed_size: usize,
+    method: u16,
+    header_offset: usize,
+}
+
+/// A zip archive read into memory. Only stored and deflated files are
+/// supported, which is all ROM sets use
+pub struct ZipArchive {
+    data: Vec<u8>,
+    pub entries: Vec<Entry>,
+}
+
+impl ZipArchive {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        ZipArchive::new(fs::read(path)?)
+    }
+
+    pub fn new(data: Vec<u8>) -> io::Result<Self> {
+        let end = find_end_of_directory(&data)?;
+        let count = read_u16(&data, end + 10)? as usize;
+        let mut offset = read_u32(&data, end + 16)? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            if read_u32(&data, offset)? != CENTRAL_HEADER_SIGNATURE {
+                return Err(invalid("corrupt central directory"));
+            }
+            let name_length = read_u16(&data, offset + 28)? as usize;
+            let extra_length = read_u16(&data, offset + 30)? as usize;
+            let comment_length = read_u16(&data, offset + 32)? as usize;
+            let name = data
+                .get(offset + 46..offset + 46 + name_length)
+                .ok_or_else(|| invalid("truncated central directory"))?;
+            let name = String::from_utf8_lossy(name);
+
+            entries.push(Entry {
+                name: name.rsplit('/').next().unwrap_or("").to_string(),
+                crc32: read_u32(&data, offset + 16)?,
+                size: read_u32(&data, offset + 24)? as usize,
+                compressed_size: read_u32(&data, offset + 20)? as usize,
+                method: read_u16(&data, offset + 10)?,
+                header_offset: read_u32(&data, offset + 42)? as usize,
+            });
+            offset += 46 + name_length + extra_length + comment_length;
+        }
+
+        Ok(ZipArchive {
+            data: data,
+            entries: entries,
+        })
+    }
+
+    /// The file called <name>, ignoring case the way MAME does
+    pub fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The file with the CRC-32 <crc>, whatever it's called
+    pub fn find_crc(&self, crc: u32) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.crc32 == crc)
+    }
+
+    /// The contents of <entry>, checked against its CRC-32
+    pub fn read(&self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let offset = entry.header_offset;
+        if read_u32(&self.data, offset)? != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid("corrupt local header"));
+        }
+        // the local header has its own name and extra field lengths
+        let start = offset
+            + 30
+            + read_u16(&self.data, offset + 26)? as usize
+            + read_u16(&self.data, offset + 28)? as usize;
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size)
+            .ok_or_else(|| invalid("truncated file"))?;
+
+        let bytes = match entry.method {
+            STORED => compressed.to_vec(),
+            DEFLATED => inflate(compressed).map_err(|why| invalid(&why))?,
+            method => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "{} uses unsupported compression method {}",
+                        entry.name, method
+                    ),
+                ))
+            }
+        };
+        if bytes.len() != entry.size || crc32(&bytes) != entry.crc32 {
+            return Err(invalid(&format!("{} is corrupt", entry.name)));
+        }
+        Ok(bytes)
+    }
+}
+
+// the record is at the end of the file, followed only by a comment of up to
+// 64 KB
+fn find_end_of_directory(data: &[u8]) -> io::Result<usize> {
+    if data.len() < END_OF_DIRECTORY_SIZE {
+        return Err(invalid("not a zip archive"));
+    }
+    let last = data.len() - END_OF_DIRECTORY_SIZE;
+    let first = last.saturating_sub(0xFFFF);
+    (first..last + 1)
+        .rev()
+        .find(|&offset| read_u32(data, offset).ok() == Some(END_OF_DIRECTORY_SIGNATURE))
+        .ok_or_else(|| invalid("not a zip archive"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(bytes[0] as u16 | (bytes[1] as u16) << 8),
+        None => Err(invalid("truncated archive")),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(read_u16(data, offset)? as u32 | (read_u16(data, offset + 2)? as u32) << 16)
+}
+
+fn invalid(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why.to_string())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A zip archive of <files>, stored without compression
+    pub fn write_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let files: Vec<_> = files
+            .iter()
+            .map(|&(name, bytes)| (name, STORED, bytes, bytes))
+            .collect();
+        zip_of(&files)
+    }
+
+    // each file is its name, compression method, data as stored and
+    // uncompressed contents
+    fn zip_of(files: &[(&str, u16, &[u8], &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, method, stored, bytes) in files {
+            let offset = data.len() as u32;
+            let mut header = Vec::new();
+            push_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+            header.extend_from_slice(&[20, 0, 0, 0]);
+            push_u16(&mut header, method);
+            header.extend_from_slice(&[0; 4]);
+            push_u32(&mut header, crc32(bytes));
+            push_u32(&mut header, stored.len() as u32);
+            push_u32(&mut header, bytes.len() as u32);
+            push_u16(&mut header, name.len() as u16);
+            push_u16(&mut header, 0);
+            data.extend_from_slice(&header);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(stored);
+
+            push_u32(&mut directory, CENTRAL_HEADER_SIGNATURE);
+            directory.extend_from_slice(&[20, 0]);
+            directory.extend_from_slice(&header[4..]);
+            directory.extend_from_slice(&[0; 10]);
+            push_u32(&mut directory, offset);
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let directory_offset = data.len() as u32;
+        data.extend_from_slice(&directory);
+        push_u32(&mut data, END_OF_DIRECTORY_SIGNATURE);
+        data.extend_from_slice(&[0; 4]);
+        push_u16(&mut data, files.len() as u16);
+        push_u16(&mut data, files.len() as u16);
+        push_u32(&mut data, directory.len() as u32);
+        push_u32(&mut data, directory_offset);
+        push_u16(&mut data, 0);
+        data
+    }
+
+    fn push_u16(data: &mut Vec<u8>, value: u16) {
+        data.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+
+    fn push_u32(data: &mut Vec<u8>, value: u32) {
+        push_u16(data, value as u16);
+        push_u16(data, (value >> 16) as u16);
+    }
+
+    #[test]
+    fn test_read() {
+        let zip =
+            ZipArchive::new(write_zip(&[("a.bin", b"abc"), ("dir/B.BIN", b"hello")])).unwrap();
+        assert_eq!(zip.entries.len(), 2);
+
+        let entry = zip.find("b.bin").unwrap();
+        assert_eq!(entry.name, "B.BIN");
+        assert_eq!(zip.read(entry).unwrap(), b"hello");
+        let entry = zip.find_crc(crc32(b"abc")).unwrap();
+        assert_eq!(entry.name, "a.bin");
+        assert!(zip.find("c.bin").is_none());
+    }
+
+    #[test]
+    fn test_deflated() {
+        let compressed = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01];
+        let contents = b"hello hello hello hello";
+        let zip = ZipArchive::new(zip_of(&[
+            ("a.txt", DEFLATED, &compressed, contents),
+            ("b.txt", 12, b"?", b"?"),
+        ]))
+        .unwrap();
+
+        assert_eq!(zip.read(&zip.entries[0]).unwrap(), contents.to_vec());
+        // bzip2 isn't supported
+        assert!(zip.read(&zip.entries[1]).is_err());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        assert!(ZipArchive::new(b"not a zip".to_vec()).is_err());
+
+        let mut data = write_zip(&[("a.bin", b"abc")]);
+        data[35] = b'x';
+        let zip = ZipArchive::new(data).unwrap();
+        assert!(zip.read(&zip.entries[0]).is_err());
+    }
+}